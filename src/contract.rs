@@ -1,10 +1,27 @@
-use crate::state::{
-    get_available_tokens, Purchase, AVAILABLE_TOKENS, CONFIG, NUMBER_OF_TOKENS_AVAILABLE,
-    PURCHASES, SALE_CONDUCTED, STATE,
+use crate::{
+    msg::{
+        CrowdfundHookMsg, CrowdfundMintMsg, EscrowStatusResponse, ExecuteMsg, InstantiateMsg,
+        OracleQueryMsg, OracleRateResponse, PayoutDestinationResponse, PriceDisplayResponse,
+        ProgressBpsResponse, ProjectedOutcome, PurchaseTaxResponse, QueryMsg,
+        RemainingAllowanceResponse, ResolvedConfigResponse, SaleOptions, TokenStatus,
+    },
+    state::{
+        add_available_token, add_revenue, append_audit_entry, get_audit_log, get_available_tokens,
+        get_number_of_tickets, get_purchasable_tokens, is_reserved_for_other,
+        remove_available_token, remove_reservation, save_reservation, subtract_revenue,
+        take_revenue, AntiSnipe, Config, Deposit, LegacyPurchase, OraclePrice, Proposal,
+        ProposedAction, Purchase, Reservation, SaleMode, SecondaryApprover, State, TokenOrder,
+        AVAILABLE_TOKENS, CONFIG, DEPOSITS, DRAW_RNG, LEGACY_CONFIG, LEGACY_PURCHASES,
+        LEGACY_STATE, MINTS_IN_BLOCK, NEXT_PROPOSAL_ID, NUMBER_OF_TICKETS,
+        NUMBER_OF_TOKENS_AVAILABLE, PENDING_PAYOUTS, PENDING_REFUNDS, PROPOSALS, PURCHASES,
+        REFUND_ADDRESSES, RESERVATIONS, RESERVER_TOKENS, REVENUE, SALE_CONDUCTED,
+        SECONDARY_APPROVER, STATE, TOKEN_PURCHASERS, TOKEN_SEQUENCES,
+    },
+    validation::{validate_bps, validate_date_range, validate_denom, validate_url, MAX_BPS},
+    ContractError,
 };
-use andromeda_non_fungible_tokens::{
-    crowdfund::{Config, CrowdfundMintMsg, ExecuteMsg, InstantiateMsg, QueryMsg, State},
-    cw721::{ExecuteMsg as Cw721ExecuteMsg, MintMsg, QueryMsg as Cw721QueryMsg},
+use andromeda_non_fungible_tokens::cw721::{
+    ExecuteMsg as Cw721ExecuteMsg, MintMsg, QueryMsg as Cw721QueryMsg,
 };
 use andromeda_std::{
     ado_base::ownership::OwnershipMessage,
@@ -20,19 +37,22 @@ use andromeda_std::{ado_contract::ADOContract, common::context::ExecuteContext};
 use andromeda_std::{
     ado_base::{hooks::AndromedaHook, InstantiateMsg as BaseInstantiateMsg, MigrateMsg},
     common::{deduct_funds, encode_binary, merge_sub_msgs, rates::get_tax_amount, Funds},
-    error::ContractError,
 };
 
 #[cfg(not(feature = "library"))]
 use cosmwasm_std::entry_point;
 use cosmwasm_std::{
-    coins, ensure, has_coins, BankMsg, Binary, Coin, CosmosMsg, Deps, DepsMut, Env, MessageInfo,
-    Order, QuerierWrapper, QueryRequest, Reply, Response, StdError, Storage, SubMsg, Uint128,
-    WasmMsg, WasmQuery,
+    coins, ensure, has_coins, BankMsg, Binary, Coin, CosmosMsg, Deps, DepsMut, Env, Event,
+    MessageInfo, Order, QuerierWrapper, QueryRequest, Reply, ReplyOn, Response, StdError,
+    StdResult, Storage, SubMsg, SubMsgResult, Uint128, Uint64, WasmMsg, WasmQuery,
+};
+use cw721::{ContractInfoResponse, OwnerOfResponse, TokensResponse};
+use cw_storage_plus::Bound;
+use cw_utils::{nonpayable, Expiration};
+use std::{
+    cmp,
+    collections::{BTreeMap, BTreeSet},
 };
-use cw721::{ContractInfoResponse, TokensResponse};
-use cw_utils::nonpayable;
-use std::cmp;
 
 const MAX_LIMIT: u32 = 100;
 const DEFAULT_LIMIT: u32 = 50;
@@ -40,6 +60,17 @@ pub(crate) const MAX_MINT_LIMIT: u32 = 100;
 const CONTRACT_NAME: &str = "crates.io:andromeda-crowdfund";
 const CONTRACT_VERSION: &str = env!("CARGO_PKG_VERSION");
 
+/// Reply ids of the submessages sent by the crowdfund, used to tell which stage failed.
+const MINT_REPLY_ID: u64 = 1;
+const TRANSFER_REPLY_ID: u64 = 2;
+const BURN_REPLY_ID: u64 = 3;
+/// Payouts are sent one per denom, the n-th payout uses `PAYOUT_REPLY_ID + n`.
+const PAYOUT_REPLY_ID: u64 = 100;
+/// Every refund uses its own id, starting at `REFUND_REPLY_ID`.
+const REFUND_REPLY_ID: u64 = 1_000;
+/// The longest a token can be reserved for unless configured otherwise, an hour.
+const DEFAULT_MAX_RESERVATION_TTL_MS: u64 = 3_600_000;
+
 #[cfg_attr(not(feature = "library"), entry_point)]
 pub fn instantiate(
     deps: DepsMut,
@@ -52,6 +83,8 @@ pub fn instantiate(
         &Config {
             token_address: msg.token_address,
             can_mint_after_sale: msg.can_mint_after_sale,
+            max_mints_per_block: msg.max_mints_per_block,
+            max_reservation_ttl_ms: msg.max_reservation_ttl_ms,
         },
     )?;
     SALE_CONDUCTED.save(deps.storage, &false)?;
@@ -79,14 +112,80 @@ pub fn instantiate(
 }
 
 #[cfg_attr(not(feature = "library"), entry_point)]
-pub fn reply(_deps: DepsMut, _env: Env, msg: Reply) -> Result<Response, ContractError> {
-    if msg.result.is_err() {
-        return Err(ContractError::Std(StdError::generic_err(
-            msg.result.unwrap_err(),
-        )));
+pub fn reply(deps: DepsMut, _env: Env, msg: Reply) -> Result<Response, ContractError> {
+    let SubMsgResult::Err(err) = msg.result else {
+        return Ok(Response::default());
+    };
+
+    match msg.id {
+        MINT_REPLY_ID => Err(stage_failed("mint", err)),
+        TRANSFER_REPLY_ID => Err(stage_failed("transfer", err)),
+        BURN_REPLY_ID => Err(stage_failed("burn", err)),
+        id if id >= REFUND_REPLY_ID => restore_failed_refund(deps, id, err),
+        id if id >= PAYOUT_REPLY_ID => restore_failed_payout(deps, id, err),
+        _ => Err(ContractError::Std(StdError::generic_err(err))),
+    }
+}
+
+fn stage_failed(stage: &str, err: String) -> ContractError {
+    ContractError::Std(StdError::generic_err(format!("{stage} failed: {err}")))
+}
+
+/// Marks the funds of a failed payout as unsent again, so that `EndSale` can retry it.
+fn restore_failed_payout(deps: DepsMut, id: u64, err: String) -> Result<Response, ContractError> {
+    let funds = PENDING_PAYOUTS.load(deps.storage, id)?;
+    PENDING_PAYOUTS.remove(deps.storage, id);
+
+    add_revenue(deps.storage, &funds)?;
+    STATE.update(deps.storage, |mut state| -> Result<_, ContractError> {
+        if state.price.denom == funds.denom {
+            state.amount_to_send = state.amount_to_send.checked_add(funds.amount)?;
+        }
+        Ok(state)
+    })?;
+
+    Ok(Response::new()
+        .add_attribute("action", "payout_failed")
+        .add_attribute("payout", funds.to_string())
+        .add_attribute("error", err))
+}
+
+/// Restores the purchases of a failed refund, so that they can be refunded again.
+fn restore_failed_refund(deps: DepsMut, id: u64, err: String) -> Result<Response, ContractError> {
+    let purchases = PENDING_REFUNDS.load(deps.storage, id)?;
+    PENDING_REFUNDS.remove(deps.storage, id);
+
+    let purchaser = purchases[0].purchaser.clone();
+    let mut restored = PURCHASES
+        .may_load(deps.storage, &purchaser)?
+        .unwrap_or_default();
+    if let Some(mut state) = STATE.may_load(deps.storage)? {
+        for refund in get_refund_amount(&purchases)? {
+            if refund.denom == state.price.denom {
+                state.amount_refunded = state.amount_refunded.checked_sub(refund.amount)?;
+            }
+        }
+        // Outside of a refunding sale, the refund was for a declined purchase, which is undone.
+        if !is_refunding(&state) {
+            if restored.is_empty() {
+                state.number_of_purchasers += 1;
+            }
+            for purchase in purchases.iter() {
+                restore_declined_purchase(deps.storage, &mut state, purchase)?;
+            }
+        }
+        STATE.save(deps.storage, &state)?;
+    }
+    for purchase in purchases.iter() {
+        TOKEN_PURCHASERS.save(deps.storage, &purchase.token_id, &purchaser)?;
     }
+    restored.extend(purchases);
+    PURCHASES.save(deps.storage, &purchaser, &restored)?;
 
-    Ok(Response::default())
+    Ok(Response::new()
+        .add_attribute("action", "refund_failed")
+        .add_attribute("purchaser", purchaser)
+        .add_attribute("error", err))
 }
 
 #[cfg_attr(not(feature = "library"), entry_point)]
@@ -99,9 +198,11 @@ pub fn execute(
     let ctx = ExecuteContext::new(deps, info, env);
 
     match msg {
-        ExecuteMsg::AMPReceive(pkt) => {
-            ADOContract::default().execute_amp_receive(ctx, pkt, handle_execute)
-        }
+        ExecuteMsg::AMPReceive(pkt) => Ok(ADOContract::default().execute_amp_receive(
+            ctx,
+            pkt,
+            |ctx, msg| handle_execute(ctx, msg).map_err(Into::into),
+        )?),
         _ => handle_execute(ctx, msg),
     }
 }
@@ -138,6 +239,7 @@ pub fn handle_execute(mut ctx: ExecuteContext, msg: ExecuteMsg) -> Result<Respon
             min_tokens_sold,
             max_amount_per_wallet,
             recipient,
+            options,
         } => execute_start_sale(
             ctx,
             start_time,
@@ -146,13 +248,48 @@ pub fn handle_execute(mut ctx: ExecuteContext, msg: ExecuteMsg) -> Result<Respon
             min_tokens_sold,
             max_amount_per_wallet,
             recipient,
+            options,
         ),
-        ExecuteMsg::Purchase { number_of_tokens } => execute_purchase(ctx, number_of_tokens),
-        ExecuteMsg::PurchaseByTokenId { token_id } => execute_purchase_by_token_id(ctx, token_id),
+        ExecuteMsg::Purchase {
+            number_of_tokens,
+            recipient,
+        } => execute_purchase(ctx, number_of_tokens, recipient),
+        ExecuteMsg::PurchaseByTokenId {
+            token_id,
+            recipient,
+        } => execute_purchase_by_token_id(ctx, token_id, recipient),
+        ExecuteMsg::BatchPurchaseByTokenId {
+            token_ids,
+            allow_partial,
+            recipient,
+        } => execute_batch_purchase_by_token_id(ctx, token_ids, allow_partial, recipient),
+        ExecuteMsg::ReserveToken { token_id, ttl_ms } => {
+            execute_reserve_token(ctx, token_id, ttl_ms)
+        }
+        ExecuteMsg::ReleaseReservation { token_id } => execute_release_reservation(ctx, token_id),
+        ExecuteMsg::SweepExpiredReservations { limit } => {
+            execute_sweep_expired_reservations(ctx, limit)
+        }
+        ExecuteMsg::PrunePurchases { limit } => execute_prune_purchases(ctx, limit),
+        ExecuteMsg::Deposit { number_of_tokens } => execute_deposit(ctx, number_of_tokens),
+        ExecuteMsg::WithdrawDeposit {} => execute_withdraw_deposit(ctx),
+        ExecuteMsg::Draw { seed, limit } => execute_draw(ctx, seed, limit),
         ExecuteMsg::ClaimRefund {} => execute_claim_refund(ctx),
+        ExecuteMsg::SetRefundAddress { address } => execute_set_refund_address(ctx, address),
+        ExecuteMsg::RefundAll { start_after, limit } => execute_refund_all(ctx, start_after, limit),
         ExecuteMsg::EndSale { limit } => execute_end_sale(ctx, limit),
+        ExecuteMsg::AbortSale { limit } => execute_abort_sale(ctx, limit),
+        ExecuteMsg::DeclinePurchase { token_id } => execute_decline_purchase(ctx, token_id),
         ExecuteMsg::UpdateTokenContract { address } => execute_update_token_contract(ctx, address),
-        _ => ADOContract::default().execute(ctx, msg),
+        ExecuteMsg::UpdateCanMintAfterSale { value } => {
+            execute_update_can_mint_after_sale(ctx, value)
+        }
+        ExecuteMsg::SetSecondaryApprover { approver } => {
+            execute_set_secondary_approver(ctx, approver)
+        }
+        ExecuteMsg::ConfirmProposal { proposal_id } => execute_confirm_proposal(ctx, proposal_id),
+        ExecuteMsg::SeedAvailability { token_ids } => execute_seed_availability(ctx, token_ids),
+        _ => Ok(ADOContract::default().execute(ctx, msg)?),
     }?;
     Ok(res
         .add_submessages(action_response.messages)
@@ -191,6 +328,21 @@ fn execute_mint(
         config.can_mint_after_sale || !sale_conducted,
         ContractError::CannotMintAfterSaleConducted {}
     );
+    if let Some(max_mints_per_block) = config.max_mints_per_block {
+        // The count starts over in every block.
+        let minted = match MINTS_IN_BLOCK.may_load(deps.storage)? {
+            Some((height, minted)) if height == env.block.height => minted,
+            _ => 0,
+        };
+        let minted = minted.saturating_add(mint_msgs.len() as u32);
+        ensure!(
+            minted <= max_mints_per_block,
+            ContractError::MintLimitPerBlockReached {
+                limit: max_mints_per_block,
+            }
+        );
+        MINTS_IN_BLOCK.save(deps.storage, &(env.block.height, minted))?;
+    }
 
     let token_contract = config.token_address;
     let crowdfund_contract = env.contract.address.to_string();
@@ -208,6 +360,7 @@ fn execute_mint(
             .add_attributes(mint_resp.attributes)
             .add_submessages(mint_resp.messages);
     }
+    append_audit_entry(deps.storage, env.block.time, info.sender.as_str(), "mint")?;
 
     Ok(resp)
 }
@@ -218,6 +371,20 @@ fn mint(
     token_contract: String,
     mint_msg: CrowdfundMintMsg,
 ) -> Result<Response, ContractError> {
+    // CHECK :: The token has metadata, either off-chain through its URI or on-chain in its
+    // extension.
+    if let Some(token_uri) = &mint_msg.token_uri {
+        validate_url("token_uri", token_uri)?;
+    }
+    ensure!(
+        mint_msg.token_uri.is_some() || !mint_msg.extension.publisher.is_empty(),
+        ContractError::CustomError {
+            msg: format!(
+                "Token {} has neither a token_uri nor an extension",
+                mint_msg.token_id
+            ),
+        }
+    );
     let mint_msg: MintMsg = MintMsg {
         token_id: mint_msg.token_id,
         owner: mint_msg
@@ -231,29 +398,34 @@ fn mint(
     // contract as the owner will be available to sell.
     if mint_msg.owner == crowdfund_contract {
         // Mark token as available to purchase in next sale.
-        AVAILABLE_TOKENS.save(storage, &mint_msg.token_id, &true)?;
+        add_available_token(storage, &mint_msg.token_id)?;
         let current_number = NUMBER_OF_TOKENS_AVAILABLE.load(storage)?;
         NUMBER_OF_TOKENS_AVAILABLE.save(storage, &(current_number + Uint128::new(1)))?;
     }
     Ok(Response::new()
         .add_attribute("action", "mint")
-        .add_message(WasmMsg::Execute {
-            contract_addr: token_contract,
-            msg: encode_binary(&Cw721ExecuteMsg::Mint {
-                token_id: mint_msg.token_id,
-                owner: mint_msg.owner,
-                token_uri: mint_msg.token_uri,
-                extension: mint_msg.extension,
-            })?,
-            funds: vec![],
-        }))
+        .add_submessage(SubMsg::reply_on_error(
+            WasmMsg::Execute {
+                contract_addr: token_contract,
+                msg: encode_binary(&Cw721ExecuteMsg::Mint {
+                    token_id: mint_msg.token_id,
+                    owner: mint_msg.owner,
+                    token_uri: mint_msg.token_uri,
+                    extension: mint_msg.extension,
+                })?,
+                funds: vec![],
+            },
+            MINT_REPLY_ID,
+        )))
 }
 
 fn execute_update_token_contract(
     ctx: ExecuteContext,
     address: AndrAddr,
 ) -> Result<Response, ContractError> {
-    let ExecuteContext { deps, info, .. } = ctx;
+    let ExecuteContext {
+        deps, info, env, ..
+    } = ctx;
     nonpayable(&info)?;
 
     let contract = ADOContract::default();
@@ -261,6 +433,65 @@ fn execute_update_token_contract(
         contract.is_contract_owner(deps.storage, info.sender.as_str())?,
         ContractError::Unauthorized {}
     );
+
+    // With a secondary approver, the update only takes effect once they confirm it.
+    if let Some(approver) = SECONDARY_APPROVER.may_load(deps.storage)? {
+        validate_token_contract(&deps.as_ref(), &address)?;
+        return propose_action(
+            deps,
+            &env,
+            info.sender.as_str(),
+            &approver,
+            ProposedAction::UpdateTokenContract { address },
+            "propose_update_token_contract",
+        );
+    }
+    update_token_contract(deps, &env, info.sender.as_str(), address)
+}
+
+/// Stores `action` as a proposal that `approver` has to confirm within their confirmation window.
+fn propose_action(
+    deps: DepsMut,
+    env: &Env,
+    actor: &str,
+    approver: &SecondaryApprover,
+    action: ProposedAction,
+    audit_action: &str,
+) -> Result<Response, ContractError> {
+    let proposal_id = NEXT_PROPOSAL_ID.may_load(deps.storage)?.unwrap_or_default();
+    NEXT_PROPOSAL_ID.save(deps.storage, &(proposal_id + 1))?;
+    let expiration = Expiration::AtTime(
+        env.block
+            .time
+            .plus_seconds(approver.confirmation_window_seconds),
+    );
+    PROPOSALS.save(deps.storage, proposal_id, &Proposal { action, expiration })?;
+    append_audit_entry(deps.storage, env.block.time, actor, audit_action)?;
+
+    Ok(Response::new()
+        .add_attribute("action", audit_action)
+        .add_attribute("proposal_id", proposal_id.to_string())
+        .add_attribute("expiration", expiration.to_string()))
+}
+
+fn update_token_contract(
+    deps: DepsMut,
+    env: &Env,
+    actor: &str,
+    address: AndrAddr,
+) -> Result<Response, ContractError> {
+    validate_token_contract(&deps.as_ref(), &address)?;
+
+    CONFIG.update(deps.storage, |mut config| {
+        config.token_address = address;
+        Ok::<_, ContractError>(config)
+    })?;
+    append_audit_entry(deps.storage, env.block.time, actor, "update_token_contract")?;
+    Ok(Response::new().add_attribute("action", "update_token_contract"))
+}
+
+/// Checks that the token contract can be changed to `address`.
+fn validate_token_contract(deps: &Deps, address: &AndrAddr) -> Result<(), ContractError> {
     // Ensure no tokens have been minted already
     let num_tokens = NUMBER_OF_TOKENS_AVAILABLE
         .load(deps.storage)
@@ -268,18 +499,214 @@ fn execute_update_token_contract(
     ensure!(num_tokens.is_zero(), ContractError::Unauthorized {});
 
     // Will error if not a valid path
-    let addr = address.get_raw_address(&deps.as_ref())?;
+    let addr = address.get_raw_address(deps)?;
     let query = Cw721QueryMsg::ContractInfo {};
 
     // Check contract is a valid CW721 contract
     let res: Result<ContractInfoResponse, StdError> = deps.querier.query_wasm_smart(addr, &query);
     ensure!(res.is_ok(), ContractError::Unauthorized {});
+    Ok(())
+}
 
-    CONFIG.update(deps.storage, |mut config| {
-        config.token_address = address;
-        Ok::<_, ContractError>(config)
+fn execute_update_can_mint_after_sale(
+    ctx: ExecuteContext,
+    value: bool,
+) -> Result<Response, ContractError> {
+    let ExecuteContext {
+        deps, info, env, ..
+    } = ctx;
+    nonpayable(&info)?;
+
+    ensure!(
+        ADOContract::default().is_contract_owner(deps.storage, info.sender.as_str())?,
+        ContractError::Unauthorized {}
+    );
+    // Can only be changed when no sale is ongoing.
+    ensure!(
+        STATE.may_load(deps.storage)?.is_none(),
+        ContractError::SaleStarted {}
+    );
+    CONFIG.update(deps.storage, |mut config| -> StdResult<_> {
+        config.can_mint_after_sale = value;
+        Ok(config)
     })?;
-    Ok(Response::new().add_attribute("action", "update_token_contract"))
+    append_audit_entry(
+        deps.storage,
+        env.block.time,
+        info.sender.as_str(),
+        "update_can_mint_after_sale",
+    )?;
+
+    Ok(Response::new()
+        .add_attribute("action", "update_can_mint_after_sale")
+        .add_attribute("can_mint_after_sale", value.to_string()))
+}
+
+fn execute_set_secondary_approver(
+    ctx: ExecuteContext,
+    approver: Option<SecondaryApprover>,
+) -> Result<Response, ContractError> {
+    let ExecuteContext {
+        deps, info, env, ..
+    } = ctx;
+    nonpayable(&info)?;
+
+    ensure!(
+        ADOContract::default().is_contract_owner(deps.storage, info.sender.as_str())?,
+        ContractError::Unauthorized {}
+    );
+    let approver = approver
+        .map(|approver| -> Result<SecondaryApprover, ContractError> {
+            ensure!(
+                approver.confirmation_window_seconds > 0,
+                ContractError::CustomError {
+                    msg: "The confirmation window must be greater than zero".to_string(),
+                }
+            );
+            Ok(SecondaryApprover {
+                address: deps.api.addr_validate(&approver.address)?.to_string(),
+                confirmation_window_seconds: approver.confirmation_window_seconds,
+            })
+        })
+        .transpose()?;
+
+    // The current approver has to confirm their own replacement or removal.
+    if let Some(current) = SECONDARY_APPROVER.may_load(deps.storage)? {
+        return propose_action(
+            deps,
+            &env,
+            info.sender.as_str(),
+            &current,
+            ProposedAction::SetSecondaryApprover { approver },
+            "propose_set_secondary_approver",
+        );
+    }
+    set_secondary_approver(deps, &env, info.sender.as_str(), approver)
+}
+
+fn set_secondary_approver(
+    deps: DepsMut,
+    env: &Env,
+    actor: &str,
+    approver: Option<SecondaryApprover>,
+) -> Result<Response, ContractError> {
+    let resp = Response::new().add_attribute("action", "set_secondary_approver");
+    let resp = match approver {
+        Some(approver) => {
+            SECONDARY_APPROVER.save(deps.storage, &approver)?;
+            resp.add_attribute("approver", approver.address)
+        }
+        None => {
+            SECONDARY_APPROVER.remove(deps.storage);
+            resp.add_attribute("approver", "none")
+        }
+    };
+    // Proposals were made for the previous approver to confirm.
+    PROPOSALS.clear(deps.storage);
+    append_audit_entry(
+        deps.storage,
+        env.block.time,
+        actor,
+        "set_secondary_approver",
+    )?;
+    Ok(resp)
+}
+
+fn execute_confirm_proposal(
+    ctx: ExecuteContext,
+    proposal_id: u64,
+) -> Result<Response, ContractError> {
+    let ExecuteContext {
+        deps, info, env, ..
+    } = ctx;
+    nonpayable(&info)?;
+
+    let approver = SECONDARY_APPROVER.may_load(deps.storage)?;
+    ensure!(
+        approver.is_some_and(|approver| approver.address == info.sender),
+        ContractError::Unauthorized {}
+    );
+    let proposal = PROPOSALS.may_load(deps.storage, proposal_id)?;
+    ensure!(
+        proposal.is_some(),
+        ContractError::CustomError {
+            msg: format!("Proposal {proposal_id} does not exist"),
+        }
+    );
+    let proposal = proposal.unwrap();
+    ensure!(
+        !proposal.expiration.is_expired(&env.block),
+        ContractError::CustomError {
+            msg: format!("Proposal {proposal_id} has expired"),
+        }
+    );
+    PROPOSALS.remove(deps.storage, proposal_id);
+
+    let resp = match proposal.action {
+        ProposedAction::UpdateTokenContract { address } => {
+            update_token_contract(deps, &env, info.sender.as_str(), address)?
+        }
+        ProposedAction::SetSecondaryApprover { approver } => {
+            set_secondary_approver(deps, &env, info.sender.as_str(), approver)?
+        }
+    };
+    Ok(resp.add_attribute("proposal_id", proposal_id.to_string()))
+}
+
+fn execute_seed_availability(
+    ctx: ExecuteContext,
+    token_ids: Vec<String>,
+) -> Result<Response, ContractError> {
+    let ExecuteContext {
+        deps, info, env, ..
+    } = ctx;
+    nonpayable(&info)?;
+
+    ensure!(
+        token_ids.len() <= MAX_MINT_LIMIT as usize,
+        ContractError::TooManyMintMessages {
+            limit: MAX_MINT_LIMIT,
+        }
+    );
+    ensure!(
+        ADOContract::default().is_contract_owner(deps.storage, info.sender.as_str())?,
+        ContractError::Unauthorized {}
+    );
+    // Can only seed when no sale is ongoing.
+    ensure!(
+        STATE.may_load(deps.storage)?.is_none(),
+        ContractError::SaleStarted {}
+    );
+
+    let config = CONFIG.load(deps.storage)?;
+    let token_address = config.token_address.get_raw_address(&deps.as_ref())?;
+    let crowdfund_contract = env.contract.address.to_string();
+
+    let mut current_number = NUMBER_OF_TOKENS_AVAILABLE.load(deps.storage)?;
+    for token_id in token_ids.iter() {
+        // CHECK :: The token was actually minted to the contract.
+        let owner = query_owner_of(&deps.querier, token_address.to_string(), token_id.clone())?;
+        ensure!(
+            owner == crowdfund_contract,
+            ContractError::TokenNotAvailable {}
+        );
+
+        if !AVAILABLE_TOKENS.has(deps.storage, token_id) {
+            add_available_token(deps.storage, token_id)?;
+            current_number = current_number.checked_add(Uint128::one())?;
+        }
+    }
+    NUMBER_OF_TOKENS_AVAILABLE.save(deps.storage, &current_number)?;
+    append_audit_entry(
+        deps.storage,
+        env.block.time,
+        info.sender.as_str(),
+        "seed_availability",
+    )?;
+
+    Ok(Response::new()
+        .add_attribute("action", "seed_availability")
+        .add_attribute("number_of_tokens_available", current_number))
 }
 
 #[allow(clippy::too_many_arguments)]
@@ -291,10 +718,26 @@ fn execute_start_sale(
     min_tokens_sold: Uint128,
     max_amount_per_wallet: Option<u32>,
     recipient: Recipient,
+    options: SaleOptions,
 ) -> Result<Response, ContractError> {
     let ExecuteContext {
         deps, info, env, ..
     } = ctx;
+    let SaleOptions {
+        sale_mode,
+        tax_inclusive,
+        min_purchase,
+        on_start_hook,
+        payout_threshold,
+        anti_snipe_window_ms,
+        anti_snipe_extension_ms,
+        anti_snipe_max_extension_ms,
+        oracle,
+        usd_price,
+        min_purchasers,
+        refund_overpayment,
+        enforce_distinct_tokens,
+    } = options;
     recipient.validate(&deps.as_ref())?;
     nonpayable(&info)?;
     let ado_contract = ADOContract::default();
@@ -309,22 +752,115 @@ fn execute_start_sale(
     let (start_expiration, _current_time) = get_and_validate_start_time(&env, start_time)?;
 
     let end_expiration = expiration_from_milliseconds(end_time)?;
-
-    ensure!(
-        end_expiration > start_expiration,
-        ContractError::StartTimeAfterEndTime {}
-    );
+    validate_date_range(&start_expiration, &end_expiration)?;
+    validate_denom("price denom", &price.denom)?;
 
     SALE_CONDUCTED.save(deps.storage, &true)?;
     let state = STATE.may_load(deps.storage)?;
     ensure!(state.is_none(), ContractError::SaleStarted {});
+    // CHECK :: Nothing of the previous sale is left, as the wallet limits count the purchases.
+    ensure!(
+        PURCHASES.is_empty(deps.storage) && TOKEN_PURCHASERS.is_empty(deps.storage),
+        ContractError::CustomError {
+            msg: "The purchases of the previous sale have not been cleared".to_string(),
+        }
+    );
     let max_amount_per_wallet = max_amount_per_wallet.unwrap_or(1u32);
+    let sale_mode = sale_mode.unwrap_or_default();
+    let tax_inclusive = tax_inclusive.unwrap_or(false);
+    // CHECK :: A wallet is allowed to purchase the minimum.
+    ensure!(
+        min_purchase.is_none_or(|min_purchase| min_purchase <= max_amount_per_wallet),
+        ContractError::CustomError {
+            msg: format!(
+                "min_purchase exceeds the max_amount_per_wallet of {max_amount_per_wallet}"
+            ),
+        }
+    );
+    let anti_snipe = match (anti_snipe_window_ms, anti_snipe_extension_ms) {
+        (Some(window_ms), Some(extension_ms)) => Some(AntiSnipe {
+            window_ms,
+            extension_ms,
+            max_extension_ms: anti_snipe_max_extension_ms.unwrap_or(extension_ms),
+            extended_ms: 0,
+        }),
+        (None, None) => None,
+        _ => {
+            return Err(ContractError::CustomError {
+                msg: "anti_snipe_window_ms and anti_snipe_extension_ms must be set together"
+                    .to_string(),
+            })
+        }
+    };
+    let oracle = match (oracle, usd_price) {
+        (Some(oracle), Some(usd_price)) => {
+            // CHECK :: Lottery deposits are made at a fixed price.
+            ensure!(
+                sale_mode == SaleMode::FirstComeFirstServed,
+                ContractError::CustomError {
+                    msg: "Oracle pricing is only supported for first come first served sales"
+                        .to_string(),
+                }
+            );
+            Some(OraclePrice {
+                address: oracle.get_raw_address(&deps.as_ref())?.to_string(),
+                usd_price,
+            })
+        }
+        (None, None) => None,
+        _ => {
+            return Err(ContractError::CustomError {
+                msg: "oracle and usd_price must be set together".to_string(),
+            })
+        }
+    };
+    let total_tokens = NUMBER_OF_TOKENS_AVAILABLE.load(deps.storage)?;
+    // CHECK :: The minimum can actually be met, otherwise the sale is guaranteed to refund.
+    ensure!(
+        min_tokens_sold <= total_tokens,
+        ContractError::CustomError {
+            msg: format!(
+                "min_tokens_sold ({min_tokens_sold}) exceeds the {total_tokens} tokens available"
+            ),
+        }
+    );
+    // CHECK :: Every purchaser buys at least one token, so there can't be more than there are
+    // tokens.
+    ensure!(
+        min_purchasers
+            .is_none_or(|min_purchasers| { Uint128::from(min_purchasers) <= total_tokens }),
+        ContractError::CustomError {
+            msg: format!("min_purchasers exceeds the {total_tokens} tokens available"),
+        }
+    );
+    let token_address = CONFIG
+        .load(deps.storage)?
+        .token_address
+        .get_raw_address(&deps.as_ref())?;
+
+    let hook_msg = on_start_hook
+        .map(|hook| -> Result<_, ContractError> {
+            Ok(WasmMsg::Execute {
+                contract_addr: hook.get_raw_address(&deps.as_ref())?.to_string(),
+                msg: encode_binary(&CrowdfundHookMsg::SaleStarted {
+                    start_time: start_expiration,
+                    end_time: end_expiration,
+                    price: price.clone(),
+                    min_tokens_sold,
+                    max_amount_per_wallet,
+                    total_tokens,
+                })?,
+                funds: vec![],
+            })
+        })
+        .transpose()?;
 
     // This is to prevent cloning price.
     let price_str = price.to_string();
     STATE.save(
         deps.storage,
         &State {
+            start_time: start_expiration,
             end_time: end_expiration,
             price,
             min_tokens_sold,
@@ -333,10 +869,34 @@ fn execute_start_sale(
             amount_to_send: Uint128::zero(),
             amount_transferred: Uint128::zero(),
             recipient,
+            total_tokens,
+            target_percentage_sold: None,
+            max_duration: None,
+            owner_ended: false,
+            token_address: token_address.to_string(),
+            sale_mode: sale_mode.clone(),
+            tax_inclusive,
+            min_purchase,
+            payout_threshold,
+            anti_snipe,
+            oracle,
+            aborted: false,
+            min_purchasers,
+            number_of_purchasers: 0,
+            refund_overpayment: refund_overpayment.unwrap_or(true),
+            enforce_distinct_tokens: enforce_distinct_tokens.unwrap_or(false),
+            amount_received: Uint128::zero(),
+            amount_refunded: Uint128::zero(),
         },
     )?;
 
     SALE_CONDUCTED.save(deps.storage, &true)?;
+    append_audit_entry(
+        deps.storage,
+        env.block.time,
+        info.sender.as_str(),
+        "start_sale",
+    )?;
 
     Ok(Response::new()
         .add_attribute("action", "start_sale")
@@ -344,12 +904,16 @@ fn execute_start_sale(
         .add_attribute("end_time", end_expiration.to_string())
         .add_attribute("price", price_str)
         .add_attribute("min_tokens_sold", min_tokens_sold)
-        .add_attribute("max_amount_per_wallet", max_amount_per_wallet.to_string()))
+        .add_attribute("max_amount_per_wallet", max_amount_per_wallet.to_string())
+        .add_attribute("sale_mode", format!("{sale_mode:?}"))
+        .add_attribute("tax_inclusive", tax_inclusive.to_string())
+        .add_messages(hook_msg))
 }
 
 fn execute_purchase_by_token_id(
     ctx: ExecuteContext,
     token_id: String,
+    recipient: Option<String>,
 ) -> Result<Response, ContractError> {
     let ExecuteContext {
         mut deps,
@@ -358,6 +922,7 @@ fn execute_purchase_by_token_id(
         ..
     } = ctx;
     let sender = info.sender.to_string();
+    let purchaser = get_purchaser(&deps, &info, recipient)?;
     let state = STATE.may_load(deps.storage)?;
 
     // CHECK :: That there is an ongoing sale.
@@ -368,40 +933,73 @@ fn execute_purchase_by_token_id(
         !state.end_time.is_expired(&env.block),
         ContractError::NoOngoingSale {}
     );
+    ensure!(
+        state.sale_mode == SaleMode::FirstComeFirstServed,
+        ContractError::UnsupportedOperation {}
+    );
 
     let mut purchases = PURCHASES
-        .may_load(deps.storage, &sender)?
+        .may_load(deps.storage, &purchaser)?
         .unwrap_or_default();
 
     ensure!(
         AVAILABLE_TOKENS.has(deps.storage, &token_id),
         ContractError::TokenNotAvailable {}
     );
+    // CHECK :: The token is not reserved for someone else.
+    ensure!(
+        !is_reserved_for_other(deps.storage, &env.block, &token_id, &sender)?,
+        ContractError::TokenNotAvailable {}
+    );
 
     let max_possible = state.max_amount_per_wallet - purchases.len() as u32;
 
     // CHECK :: The user is able to purchase these without going over the limit.
     ensure!(max_possible > 0, ContractError::PurchaseLimitReached {});
+    ensure_min_purchase(deps.storage, &state, 1)?;
 
-    purchase_tokens(
+    let required_payment = purchase_tokens(
         &mut deps,
+        &env,
         vec![token_id.clone()],
         &info,
+        &purchaser,
+        &mut state,
+        &mut purchases,
+    )?;
+    let mut funds = info.funds.clone();
+    deduct_funds(&mut funds, &required_payment)?;
+    keep_overpayment(
+        deps.storage,
         &mut state,
         &mut purchases,
+        &mut funds,
+        Uint128::zero(),
     )?;
+    let payout_msgs = get_partial_payout_messages(&mut deps, &info, &env, &mut state)?;
 
     STATE.save(deps.storage, &state)?;
-    PURCHASES.save(deps.storage, &sender, &purchases)?;
+    PURCHASES.save(deps.storage, &purchaser, &purchases)?;
 
-    Ok(Response::new()
+    let mut resp = Response::new().add_submessages(payout_msgs);
+    if has_coins(&funds, &Coin::new(1, state.price.denom)) {
+        resp = resp.add_message(BankMsg::Send {
+            to_address: sender,
+            amount: funds,
+        });
+    }
+
+    Ok(resp
         .add_attribute("action", "purchase")
-        .add_attribute("token_id", token_id))
+        .add_attribute("token_id", token_id)
+        .add_attribute("recipient", purchaser))
 }
 
-fn execute_purchase(
+fn execute_batch_purchase_by_token_id(
     ctx: ExecuteContext,
-    number_of_tokens: Option<u32>,
+    token_ids: Vec<String>,
+    allow_partial: bool,
+    recipient: Option<String>,
 ) -> Result<Response, ContractError> {
     let ExecuteContext {
         mut deps,
@@ -410,6 +1008,7 @@ fn execute_purchase(
         ..
     } = ctx;
     let sender = info.sender.to_string();
+    let purchaser = get_purchaser(&deps, &info, recipient)?;
     let state = STATE.may_load(deps.storage)?;
 
     // CHECK :: That there is an ongoing sale.
@@ -420,44 +1019,88 @@ fn execute_purchase(
         !state.end_time.is_expired(&env.block),
         ContractError::NoOngoingSale {}
     );
+    ensure!(
+        state.sale_mode == SaleMode::FirstComeFirstServed,
+        ContractError::UnsupportedOperation {}
+    );
 
     let mut purchases = PURCHASES
-        .may_load(deps.storage, &sender)?
+        .may_load(deps.storage, &purchaser)?
         .unwrap_or_default();
 
     let max_possible = state.max_amount_per_wallet - purchases.len() as u32;
 
     // CHECK :: The user is able to purchase these without going over the limit.
     ensure!(max_possible > 0, ContractError::PurchaseLimitReached {});
+    ensure!(
+        allow_partial || token_ids.len() <= max_possible as usize,
+        ContractError::PurchaseLimitReached {}
+    );
 
-    let number_of_tokens_wanted =
-        number_of_tokens.map_or(max_possible, |n| cmp::min(n, max_possible));
-
-    // The number of token ids here is equal to min(number_of_tokens_wanted, num_tokens_left).
-    let token_ids = get_available_tokens(deps.storage, None, Some(number_of_tokens_wanted))?;
-
-    let number_of_tokens_purchased = token_ids.len();
-
-    let required_payment =
-        purchase_tokens(&mut deps, token_ids, &info, &mut state, &mut purchases)?;
+    let number_of_tokens_wanted = token_ids.len();
+    let mut purchasable = vec![];
+    // CHECK :: Every id is listed only once, when the sale enforces it.
+    if state.enforce_distinct_tokens {
+        let mut listed = BTreeSet::new();
+        for token_id in token_ids.iter() {
+            ensure!(
+                listed.insert(token_id),
+                ContractError::CustomError {
+                    msg: format!("Token id {token_id} is listed more than once"),
+                }
+            );
+        }
+    }
+    for token_id in token_ids {
+        // A repeated id has already been taken by its first occurrence.
+        let is_purchasable = AVAILABLE_TOKENS.has(deps.storage, &token_id)
+            && !is_reserved_for_other(deps.storage, &env.block, &token_id, &sender)?
+            && !purchasable.contains(&token_id);
+        if is_purchasable {
+            purchasable.push(token_id);
+        } else {
+            // CHECK :: Every token is available, unless a partial purchase is fine.
+            ensure!(allow_partial, ContractError::TokenNotAvailable {});
+        }
+    }
+    purchasable.truncate(max_possible as usize);
+    let number_of_tokens_purchased = purchasable.len();
+    ensure_min_purchase(deps.storage, &state, number_of_tokens_purchased)?;
 
-    PURCHASES.save(deps.storage, &sender, &purchases)?;
-    STATE.save(deps.storage, &state)?;
-
-    // Refund user if they sent more. This can happen near the end of the sale when they weren't
-    // able to get the amount that they wanted.
-    let mut funds = info.funds;
+    let required_payment = purchase_tokens(
+        &mut deps,
+        &env,
+        purchasable,
+        &info,
+        &purchaser,
+        &mut state,
+        &mut purchases,
+    )?;
+    // Refund the payment for the tokens that were not purchased.
+    let mut funds = info.funds.clone();
     deduct_funds(&mut funds, &required_payment)?;
+    let unfilled_amount = state.price.amount.checked_mul(Uint128::from(
+        (number_of_tokens_wanted - number_of_tokens_purchased) as u128,
+    ))?;
+    keep_overpayment(
+        deps.storage,
+        &mut state,
+        &mut purchases,
+        &mut funds,
+        unfilled_amount,
+    )?;
+    let payout_msgs = get_partial_payout_messages(&mut deps, &info, &env, &mut state)?;
 
-    // If any funds were remaining after deduction, send refund.
-    let resp = if has_coins(&funds, &Coin::new(1, state.price.denom)) {
-        Response::new().add_message(BankMsg::Send {
+    PURCHASES.save(deps.storage, &purchaser, &purchases)?;
+    STATE.save(deps.storage, &state)?;
+
+    let mut resp = Response::new().add_submessages(payout_msgs);
+    if has_coins(&funds, &Coin::new(1, state.price.denom)) {
+        resp = resp.add_message(BankMsg::Send {
             to_address: sender,
             amount: funds,
-        })
-    } else {
-        Response::new()
-    };
+        });
+    }
 
     Ok(resp
         .add_attribute("action", "purchase")
@@ -468,588 +1111,5725 @@ fn execute_purchase(
         .add_attribute(
             "number_of_tokens_purchased",
             number_of_tokens_purchased.to_string(),
-        ))
+        )
+        .add_attribute("recipient", purchaser))
 }
 
-fn purchase_tokens(
-    deps: &mut DepsMut,
-    token_ids: Vec<String>,
-    info: &MessageInfo,
-    state: &mut State,
-    purchases: &mut Vec<Purchase>,
-) -> Result<Coin, ContractError> {
-    // CHECK :: There are any tokens left to purchase.
-    ensure!(!token_ids.is_empty(), ContractError::AllTokensPurchased {});
+fn execute_purchase(
+    ctx: ExecuteContext,
+    number_of_tokens: Option<u32>,
+    recipient: Option<String>,
+) -> Result<Response, ContractError> {
+    let ExecuteContext {
+        mut deps,
+        info,
+        env,
+        ..
+    } = ctx;
+    let sender = info.sender.to_string();
+    let purchaser = get_purchaser(&deps, &info, recipient)?;
+    let state = STATE.may_load(deps.storage)?;
 
-    let number_of_tokens_purchased = token_ids.len();
+    // CHECK :: That there is an ongoing sale.
+    ensure!(state.is_some(), ContractError::NoOngoingSale {});
 
-    // CHECK :: The user has sent enough funds to cover the base fee (without any taxes).
-    let total_cost = Coin::new(
-        state.price.amount.u128() * number_of_tokens_purchased as u128,
-        state.price.denom.clone(),
+    let mut state = state.unwrap();
+    ensure!(
+        !state.end_time.is_expired(&env.block),
+        ContractError::NoOngoingSale {}
     );
     ensure!(
-        has_coins(&info.funds, &total_cost),
-        ContractError::InsufficientFunds {}
+        state.sale_mode == SaleMode::FirstComeFirstServed,
+        ContractError::UnsupportedOperation {}
     );
 
-    let mut total_tax_amount = Uint128::zero();
+    let mut purchases = PURCHASES
+        .may_load(deps.storage, &purchaser)?
+        .unwrap_or_default();
 
-    // This is the same for each token, so we only need to do it once.
-    let (msgs, _events, remainder) = ADOContract::default().on_funds_transfer(
-        &deps.as_ref(),
-        info.sender.to_string(),
-        Funds::Native(state.price.clone()),
-        encode_binary(&"")?,
+    let max_possible = state.max_amount_per_wallet - purchases.len() as u32;
+
+    // CHECK :: The user is able to purchase these without going over the limit.
+    ensure!(max_possible > 0, ContractError::PurchaseLimitReached {});
+
+    let number_of_tokens_wanted =
+        number_of_tokens.map_or(max_possible, |n| cmp::min(n, max_possible));
+
+    // The number of token ids here is equal to min(number_of_tokens_wanted, num_tokens_left),
+    // not counting tokens reserved for other purchasers.
+    let token_ids =
+        get_purchasable_tokens(deps.storage, &env.block, &sender, number_of_tokens_wanted)?;
+
+    let number_of_tokens_purchased = token_ids.len();
+    ensure_min_purchase(deps.storage, &state, number_of_tokens_purchased)?;
+    let shortfall = number_of_tokens_wanted - number_of_tokens_purchased as u32;
+
+    let required_payment = purchase_tokens(
+        &mut deps,
+        &env,
+        token_ids,
+        &info,
+        &purchaser,
+        &mut state,
+        &mut purchases,
     )?;
 
-    let mut current_number = NUMBER_OF_TOKENS_AVAILABLE.load(deps.storage)?;
-    for token_id in token_ids {
-        let remaining_amount = remainder.try_get_coin()?;
+    // Refund user if they sent more. This can happen near the end of the sale when they weren't
+    // able to get the amount that they wanted.
+    let mut funds = info.funds.clone();
+    deduct_funds(&mut funds, &required_payment)?;
+    let unfilled_amount = state.price.amount.checked_mul(Uint128::from(shortfall))?;
+    keep_overpayment(
+        deps.storage,
+        &mut state,
+        &mut purchases,
+        &mut funds,
+        unfilled_amount,
+    )?;
+    let payout_msgs = get_partial_payout_messages(&mut deps, &info, &env, &mut state)?;
 
-        let tax_amount = get_tax_amount(&msgs, state.price.amount, remaining_amount.amount);
+    PURCHASES.save(deps.storage, &purchaser, &purchases)?;
+    STATE.save(deps.storage, &state)?;
 
-        let purchase = Purchase {
-            token_id: token_id.clone(),
-            tax_amount,
-            msgs: msgs.clone(),
-            purchaser: info.sender.to_string(),
-        };
-        total_tax_amount = total_tax_amount.checked_add(tax_amount)?;
+    let refund_amount = funds
+        .iter()
+        .find(|coin| coin.denom == state.price.denom)
+        .map_or(Uint128::zero(), |coin| coin.amount);
 
-        state.amount_to_send = state.amount_to_send.checked_add(remaining_amount.amount)?;
-        state.amount_sold = state.amount_sold.checked_add(Uint128::one())?;
+    let mut resp = Response::new().add_submessages(payout_msgs);
+    // Signal that the user did not get everything they asked for, so front-ends can notify them.
+    if shortfall > 0 {
+        resp = resp.add_event(
+            Event::new("purchase_shortfall")
+                .add_attribute("purchaser", sender.clone())
+                .add_attribute(
+                    "number_of_tokens_wanted",
+                    number_of_tokens_wanted.to_string(),
+                )
+                .add_attribute(
+                    "number_of_tokens_purchased",
+                    number_of_tokens_purchased.to_string(),
+                )
+                .add_attribute("shortfall", shortfall.to_string())
+                .add_attribute("refund_amount", refund_amount),
+        );
+    }
 
-        purchases.push(purchase);
+    // If any funds were remaining after deduction, send refund.
+    if has_coins(&funds, &Coin::new(1, state.price.denom)) {
+        resp = resp.add_message(BankMsg::Send {
+            to_address: sender,
+            amount: funds,
+        });
+    }
 
-        AVAILABLE_TOKENS.remove(deps.storage, &token_id);
-        current_number = current_number.checked_sub(Uint128::one())?;
+    Ok(resp
+        .add_attribute("action", "purchase")
+        .add_attribute(
+            "number_of_tokens_wanted",
+            number_of_tokens_wanted.to_string(),
+        )
+        .add_attribute(
+            "number_of_tokens_purchased",
+            number_of_tokens_purchased.to_string(),
+        )
+        .add_attribute("shortfall", shortfall.to_string())
+        .add_attribute("recipient", purchaser))
+}
+
+/// Keeps the sale denom left over from a purchase for the recipient as a tip, unless the sale
+/// refunds overpayments. What the tokens that were wanted but couldn't be purchased would have
+/// cost, `unfilled_amount`, is refunded either way. The tip is recorded on the last of
+/// `purchases`, so that it is refunded along with them if the sale fails.
+fn keep_overpayment(
+    storage: &mut dyn Storage,
+    state: &mut State,
+    purchases: &mut [Purchase],
+    funds: &mut Vec<Coin>,
+    unfilled_amount: Uint128,
+) -> Result<(), ContractError> {
+    if state.refund_overpayment {
+        return Ok(());
     }
-    NUMBER_OF_TOKENS_AVAILABLE.save(deps.storage, &current_number)?;
+    let Some(coin) = funds
+        .iter_mut()
+        .find(|coin| coin.denom == state.price.denom)
+    else {
+        return Ok(());
+    };
+    let tip = coin.amount.saturating_sub(unfilled_amount);
+    if tip.is_zero() {
+        return Ok(());
+    }
+    coin.amount -= tip;
+    funds.retain(|coin| !coin.amount.is_zero());
 
-    // CHECK :: User has sent enough to cover taxes.
-    let required_payment = Coin {
-        denom: state.price.denom.clone(),
-        amount: state
-            .price
-            .amount
-            .checked_mul(Uint128::from(number_of_tokens_purchased as u128))?
-            .checked_add(total_tax_amount)?,
+    state.amount_to_send = state.amount_to_send.checked_add(tip)?;
+    state.amount_received = state.amount_received.checked_add(tip)?;
+    add_revenue(storage, &Coin::new(tip.u128(), state.price.denom.clone()))?;
+    if let Some(purchase) = purchases.last_mut() {
+        purchase.tip_amount = purchase.tip_amount.checked_add(tip)?;
+    }
+    Ok(())
+}
+
+/// Pays the revenue collected so far out to the recipient once `amount_to_send` reaches the
+/// sale's payout threshold, so that less is left to pay out when the sale ends. Nothing is paid
+/// out before the minimum number of tokens has been sold, as the sale can still fail until then
+/// and every purchase has to remain refundable.
+fn get_partial_payout_messages(
+    deps: &mut DepsMut,
+    info: &MessageInfo,
+    env: &Env,
+    state: &mut State,
+) -> Result<Vec<SubMsg>, ContractError> {
+    let Some(payout_threshold) = state.payout_threshold else {
+        return Ok(vec![]);
+    };
+    if !is_minimum_met(state) || state.amount_to_send < payout_threshold {
+        return Ok(vec![]);
+    }
+
+    let revenue = take_revenue(deps.storage)?;
+    track_pending_payouts(deps.storage, &revenue)?;
+    state.amount_to_send = Uint128::zero();
+    get_payout_messages(&deps.as_ref(), info, env, &state.recipient, revenue)
+}
+
+/// Checks that a purchase of `number_of_tokens` meets the sale's minimum purchase, which is waived
+/// once fewer tokens than the minimum remain so that the last tokens can still be sold.
+fn ensure_min_purchase(
+    storage: &dyn Storage,
+    state: &State,
+    number_of_tokens: usize,
+) -> Result<(), ContractError> {
+    let Some(min_purchase) = state.min_purchase else {
+        return Ok(());
     };
+    let number_of_tokens_available = NUMBER_OF_TOKENS_AVAILABLE.load(storage)?;
     ensure!(
-        has_coins(&info.funds, &required_payment),
-        ContractError::InsufficientFunds {}
+        number_of_tokens >= min_purchase as usize
+            || number_of_tokens_available < Uint128::from(min_purchase),
+        ContractError::CustomError {
+            msg: format!("Must purchase at least {min_purchase} tokens"),
+        }
     );
-    Ok(required_payment)
+    Ok(())
 }
 
-fn execute_claim_refund(ctx: ExecuteContext) -> Result<Response, ContractError> {
+/// Returns who a purchase is made for, which is the validated `recipient` if given and the
+/// sender otherwise.
+fn get_purchaser(
+    deps: &DepsMut,
+    info: &MessageInfo,
+    recipient: Option<String>,
+) -> Result<String, ContractError> {
+    match recipient {
+        Some(recipient) => Ok(deps.api.addr_validate(&recipient)?.to_string()),
+        None => Ok(info.sender.to_string()),
+    }
+}
+
+fn execute_reserve_token(
+    ctx: ExecuteContext,
+    token_id: String,
+    ttl_ms: u64,
+) -> Result<Response, ContractError> {
     let ExecuteContext {
         deps, info, env, ..
     } = ctx;
     nonpayable(&info)?;
-
+    let sender = info.sender.to_string();
     let state = STATE.may_load(deps.storage)?;
+
+    // CHECK :: That there is an ongoing sale.
     ensure!(state.is_some(), ContractError::NoOngoingSale {});
+
     let state = state.unwrap();
     ensure!(
-        state.end_time.is_expired(&env.block),
-        ContractError::SaleNotEnded {}
+        !state.end_time.is_expired(&env.block),
+        ContractError::NoOngoingSale {}
+    );
+    ensure!(
+        state.sale_mode == SaleMode::FirstComeFirstServed,
+        ContractError::UnsupportedOperation {}
+    );
+    ensure!(ttl_ms > 0, ContractError::InvalidZeroAmount {});
+    let max_ttl_ms = CONFIG
+        .load(deps.storage)?
+        .max_reservation_ttl_ms
+        .unwrap_or(DEFAULT_MAX_RESERVATION_TTL_MS);
+    ensure!(
+        ttl_ms <= max_ttl_ms,
+        ContractError::CustomError {
+            msg: format!("A token can't be reserved for more than {max_ttl_ms} milliseconds"),
+        }
+    );
+    ensure!(
+        AVAILABLE_TOKENS.has(deps.storage, &token_id),
+        ContractError::TokenNotAvailable {}
     );
     ensure!(
-        state.amount_sold < state.min_tokens_sold,
-        ContractError::MinSalesExceeded {}
+        !is_reserved_for_other(deps.storage, &env.block, &token_id, &sender)?,
+        ContractError::TokenNotAvailable {}
     );
 
-    let purchases = PURCHASES.may_load(deps.storage, info.sender.as_str())?;
-    ensure!(purchases.is_some(), ContractError::NoPurchases {});
-    let purchases = purchases.unwrap();
-    let refund_msg = process_refund(deps.storage, &purchases, &state.price);
-    let mut resp = Response::new();
-    if let Some(refund_msg) = refund_msg {
-        resp = resp.add_message(refund_msg);
+    // CHECK :: The sender doesn't hold more reservations than they can purchase, not counting the
+    // one of this token, which is renewed. Their lapsed reservations don't hold anything anymore,
+    // so they are removed on the way.
+    let mut number_of_reservations = 0;
+    let reserved: Vec<(String, Expiration)> = RESERVER_TOKENS
+        .prefix(&sender)
+        .range(deps.storage, None, None, Order::Ascending)
+        .collect::<StdResult<_>>()?;
+    for (reserved_token_id, expiration) in reserved {
+        if expiration.is_expired(&env.block) {
+            remove_reservation(deps.storage, &reserved_token_id)?;
+        } else if reserved_token_id != token_id {
+            number_of_reservations += 1;
+        }
     }
+    let number_of_purchases = PURCHASES
+        .may_load(deps.storage, &sender)?
+        .map_or(0, |purchases| purchases.len() as u32);
+    ensure!(
+        number_of_purchases + number_of_reservations < state.max_amount_per_wallet,
+        ContractError::PurchaseLimitReached {}
+    );
 
-    Ok(resp.add_attribute("action", "claim_refund"))
-}
-fn end_condition_met(state: &State, env: &Env) -> bool {
-    // Check if the sale has reached its end time
-    let is_sale_expired = state.end_time.is_expired(&env.block);
+    let ttl_nanos = Uint64::new(ttl_ms).checked_mul(Uint64::new(1_000_000))?;
+    let expiration = Expiration::AtTime(env.block.time.plus_nanos(ttl_nanos.u64()));
+    save_reservation(
+        deps.storage,
+        &token_id,
+        &Reservation {
+            reserver: sender,
+            expiration,
+        },
+    )?;
 
-    // Check if the minimum tokens sold condition is met
-    let is_minimum_sold = state.amount_sold >= state.min_tokens_sold;
+    Ok(Response::new()
+        .add_attribute("action", "reserve_token")
+        .add_attribute("token_id", token_id)
+        .add_attribute("expiration", expiration.to_string()))
+}
 
-    // Check if the target percentage of tokens sold condition is met
-    let is_target_percentage_sold = match state.target_percentage_sold {
-        Some(target_percentage) => {
-            let sold_percentage = state.amount_sold.u128() * 100 / state.total_tokens.u128();
-            sold_percentage >= target_percentage
-        }
-        None => false, // No target percentage set, so this condition is always false
-    };
+fn execute_release_reservation(
+    ctx: ExecuteContext,
+    token_id: String,
+) -> Result<Response, ContractError> {
+    let ExecuteContext { deps, info, .. } = ctx;
+    nonpayable(&info)?;
 
-    // Check if the maximum duration condition is met
-    let is_max_duration_reached = match state.max_duration {
-        Some(max_duration) => {
-            let duration_elapsed = env.block.time - state.start_time;
-            duration_elapsed >= max_duration
+    let reservation = RESERVATIONS.may_load(deps.storage, &token_id)?;
+    ensure!(
+        reservation.is_some(),
+        ContractError::CustomError {
+            msg: format!("Token {token_id} is not reserved"),
         }
-        None => false, // No maximum duration set, so this condition is always false
-    };
-
-    // Check if the owner has manually ended the sale
-    let is_owner_ended = state.owner_ended;
+    );
+    ensure!(
+        reservation.unwrap().reserver == info.sender,
+        ContractError::Unauthorized {}
+    );
+    remove_reservation(deps.storage, &token_id)?;
 
-    // The end condition is met if any of the conditions are true
-    is_sale_expired
-        || is_minimum_sold
-        || is_target_percentage_sold
-        || is_max_duration_reached
-        || is_owner_ended
+    Ok(Response::new()
+        .add_attribute("action", "release_reservation")
+        .add_attribute("token_id", token_id))
 }
 
-fn execute_end_sale(
+fn execute_sweep_expired_reservations(
     ctx: ExecuteContext,
-    end_condition_met: bool,
+    limit: Option<u32>,
 ) -> Result<Response, ContractError> {
     let ExecuteContext {
-        mut deps,
-        info,
-        env,
-        ..
+        deps, info, env, ..
     } = ctx;
     nonpayable(&info)?;
 
-    let mut state = STATE.load(deps.storage)?;
-    let number_of_tokens_available = NUMBER_OF_TOKENS_AVAILABLE.load(deps.storage)?;
-
-    let is_owner = ADOContract::default().is_contract_owner(deps.storage, &info.sender)?;
-
-    if end_condition_met
-        || state.end_time.is_expired(&env.block)
-        || number_of_tokens_available.is_zero()
-        || is_owner
-    {
-        // Proceed with sale completion steps
-        transfer_tokens_and_send_funds(&mut deps, info.clone(), env)
-    } else {
-        // Continue with the sale until the end condition is met or the owner decides to end it
-        Ok(Response::default())
-    }
-}
-fn issue_refunds_and_burn_tokens(
-    deps: &mut DepsMut,
-    env: Env,
-    limit: Option<u32>,
-) -> Result<Response, ContractError> {
-    let state = STATE.load(deps.storage)?;
     let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
     ensure!(limit > 0, ContractError::LimitMustNotBeZero {});
-    let mut refund_msgs: Vec<CosmosMsg> = vec![];
-    // Issue refunds for `limit` number of users.
-    let purchases: Vec<Vec<Purchase>> = PURCHASES
+    let expired: Vec<String> = RESERVATIONS
         .range(deps.storage, None, None, Order::Ascending)
         .take(limit)
+        .filter_map(|item| match item {
+            Ok((token_id, reservation)) if reservation.expiration.is_expired(&env.block) => {
+                Some(Ok(token_id))
+            }
+            Ok(_) => None,
+            Err(err) => Some(Err(err)),
+        })
+        .collect::<StdResult<_>>()?;
+    for token_id in expired.iter() {
+        remove_reservation(deps.storage, token_id)?;
+    }
+
+    Ok(Response::new()
+        .add_attribute("action", "sweep_expired_reservations")
+        .add_attribute("number_of_swept", expired.len().to_string()))
+}
+
+fn execute_prune_purchases(
+    ctx: ExecuteContext,
+    limit: Option<u32>,
+) -> Result<Response, ContractError> {
+    let ExecuteContext {
+        deps, info, env, ..
+    } = ctx;
+    nonpayable(&info)?;
+
+    let state = STATE.may_load(deps.storage)?;
+    // CHECK :: The purchases are no longer needed to transfer the tokens or to enforce the
+    // wallet limits.
+    if let Some(state) = &state {
+        ensure!(
+            state.end_time.is_expired(&env.block) && is_refunding(state),
+            ContractError::SaleNotEnded {}
+        );
+    }
+
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    ensure!(limit > 0, ContractError::LimitMustNotBeZero {});
+    let entries: Vec<(String, Vec<Purchase>)> = PURCHASES
+        .range(deps.storage, None, None, Order::Ascending)
+        .take(limit)
+        .collect::<StdResult<_>>()?;
+    let mut number_of_pruned = 0;
+    for (purchaser, purchases) in entries {
+        // Nothing is owed anymore once the sale is over.
+        if state.is_none() || get_refund_amount(&purchases)?.is_empty() {
+            PURCHASES.remove(deps.storage, &purchaser);
+            for purchase in purchases.iter() {
+                TOKEN_PURCHASERS.remove(deps.storage, &purchase.token_id);
+            }
+            number_of_pruned += 1;
+        }
+    }
+
+    Ok(Response::new()
+        .add_attribute("action", "prune_purchases")
+        .add_attribute("number_of_pruned", number_of_pruned.to_string()))
+}
+
+fn execute_deposit(
+    ctx: ExecuteContext,
+    number_of_tokens: Option<u32>,
+) -> Result<Response, ContractError> {
+    let ExecuteContext {
+        deps, info, env, ..
+    } = ctx;
+    let sender = info.sender.to_string();
+    let state = STATE.may_load(deps.storage)?;
+
+    // CHECK :: That there is an ongoing lottery sale.
+    ensure!(state.is_some(), ContractError::NoOngoingSale {});
+
+    let state = state.unwrap();
+    ensure!(
+        !state.end_time.is_expired(&env.block),
+        ContractError::NoOngoingSale {}
+    );
+    ensure!(
+        state.sale_mode == SaleMode::Lottery,
+        ContractError::UnsupportedOperation {}
+    );
+
+    let tickets = get_number_of_tickets(deps.storage, &sender)?;
+
+    let max_possible = state.max_amount_per_wallet - tickets;
+
+    // CHECK :: The user is able to deposit for these without going over the limit.
+    ensure!(max_possible > 0, ContractError::PurchaseLimitReached {});
+
+    let number_of_tickets = number_of_tokens.map_or(max_possible, |n| cmp::min(n, max_possible));
+    ensure!(number_of_tickets > 0, ContractError::InvalidZeroAmount {});
+
+    // The rates apply to each ticket as they do to each purchased token.
+    let (msgs, _events, remainder) = ADOContract::default().on_funds_transfer(
+        &deps.as_ref(),
+        sender.clone(),
+        Funds::Native(state.price.clone()),
+        encode_binary(&"")?,
+    )?;
+    let remaining_amount = remainder.try_get_coin()?;
+    let tax_amount = get_tax_amount(&msgs, state.price.amount, remaining_amount.amount);
+    let (tax_amount, amount_to_send) = get_token_payment(
+        state.price.amount,
+        tax_amount,
+        remaining_amount.amount,
+        state.tax_inclusive,
+    )?;
+    let msgs = if state.tax_inclusive {
+        scale_rate_messages(&msgs, state.price.amount.checked_sub(amount_to_send)?)?
+    } else {
+        msgs
+    };
+
+    // CHECK :: The user has sent enough funds to cover every ticket.
+    let required_payment = Coin {
+        denom: state.price.denom.clone(),
+        amount: state
+            .price
+            .amount
+            .checked_add(tax_amount)?
+            .checked_mul(Uint128::from(number_of_tickets))?,
+    };
+    ensure!(
+        has_coins(&info.funds, &required_payment),
+        ContractError::InsufficientFunds {}
+    );
+
+    let mut deposits = DEPOSITS
+        .may_load(deps.storage, &sender)?
+        .unwrap_or_default();
+    deposits.push(Deposit {
+        number_of_tickets,
+        tax_amount,
+        amount_to_send,
+        msgs,
+    });
+    DEPOSITS.save(deps.storage, &sender, &deposits)?;
+    let total_tickets = NUMBER_OF_TICKETS
+        .may_load(deps.storage)?
+        .unwrap_or_default();
+    NUMBER_OF_TICKETS.save(deps.storage, &(total_tickets + number_of_tickets))?;
+
+    let mut funds = info.funds;
+    deduct_funds(&mut funds, &required_payment)?;
+
+    // If any funds were remaining after deduction, send refund.
+    let resp = if has_coins(&funds, &Coin::new(1, state.price.denom)) {
+        Response::new().add_message(BankMsg::Send {
+            to_address: sender,
+            amount: funds,
+        })
+    } else {
+        Response::new()
+    };
+
+    Ok(resp
+        .add_attribute("action", "deposit")
+        .add_attribute("number_of_tickets", number_of_tickets.to_string()))
+}
+
+/// What was paid for each ticket of `deposit`.
+fn get_ticket_cost(state: &State, deposit: &Deposit) -> Result<Uint128, ContractError> {
+    Ok(state.price.amount.checked_add(deposit.tax_amount)?)
+}
+
+fn execute_withdraw_deposit(ctx: ExecuteContext) -> Result<Response, ContractError> {
+    let ExecuteContext {
+        deps, info, env, ..
+    } = ctx;
+    nonpayable(&info)?;
+
+    let state = STATE.may_load(deps.storage)?;
+    ensure!(state.is_some(), ContractError::NoOngoingSale {});
+    let state = state.unwrap();
+    ensure!(
+        state.sale_mode == SaleMode::Lottery,
+        ContractError::UnsupportedOperation {}
+    );
+    ensure!(
+        state.end_time.is_expired(&env.block),
+        ContractError::SaleNotEnded {}
+    );
+    // CHECK :: The deposits are not being drawn.
+    ensure!(
+        !DRAW_RNG.exists(deps.storage),
+        ContractError::CustomError {
+            msg: "The draw has already started".to_string(),
+        }
+    );
+
+    let sender = info.sender.to_string();
+    let deposits = DEPOSITS
+        .may_load(deps.storage, &sender)?
+        .unwrap_or_default();
+    ensure!(!deposits.is_empty(), ContractError::NoPurchases {});
+    DEPOSITS.remove(deps.storage, &sender);
+
+    let mut number_of_tickets = 0;
+    let mut amount = Uint128::zero();
+    for deposit in deposits.iter() {
+        number_of_tickets += deposit.number_of_tickets;
+        amount = amount.checked_add(
+            get_ticket_cost(&state, deposit)?
+                .checked_mul(Uint128::from(deposit.number_of_tickets))?,
+        )?;
+    }
+    NUMBER_OF_TICKETS.update(deps.storage, |total_tickets| -> StdResult<_> {
+        Ok(total_tickets.saturating_sub(number_of_tickets))
+    })?;
+
+    let to_address = REFUND_ADDRESSES
+        .may_load(deps.storage, &sender)?
+        .unwrap_or(sender);
+    Ok(Response::new()
+        .add_message(BankMsg::Send {
+            to_address,
+            amount: coins(amount.u128(), state.price.denom),
+        })
+        .add_attribute("action", "withdraw_deposit")
+        .add_attribute("number_of_tickets", number_of_tickets.to_string()))
+}
+
+/// Draws winners among the tickets of the next `limit` depositors of an ended lottery sale.
+/// Winners are recorded in `PURCHASES` so that `EndSale` can complete the sale as usual, and the
+/// tickets that were not drawn are refunded.
+///
+/// Each ticket is drawn with the chance that one of the tokens left is allocated to it among the
+/// tickets left, which gives every ticket the same chance however the draw is batched.
+fn execute_draw(
+    ctx: ExecuteContext,
+    seed: u64,
+    limit: Option<u32>,
+) -> Result<Response, ContractError> {
+    let ExecuteContext {
+        deps, info, env, ..
+    } = ctx;
+    nonpayable(&info)?;
+
+    let state = STATE.may_load(deps.storage)?;
+    ensure!(state.is_some(), ContractError::NoOngoingSale {});
+    let mut state = state.unwrap();
+    ensure!(
+        state.sale_mode == SaleMode::Lottery,
+        ContractError::UnsupportedOperation {}
+    );
+    ensure!(
+        state.end_time.is_expired(&env.block),
+        ContractError::SaleNotEnded {}
+    );
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    ensure!(limit > 0, ContractError::LimitMustNotBeZero {});
+
+    let mut rng = match DRAW_RNG.may_load(deps.storage)? {
+        // A draw that has started continues its sequence, so the seed only matters to start it.
+        Some(rng) => DrawRng(rng),
+        None => {
+            ensure!(
+                ADOContract::default().is_contract_owner(deps.storage, info.sender.as_str())?,
+                ContractError::Unauthorized {}
+            );
+            ensure!(
+                !DEPOSITS.is_empty(deps.storage),
+                ContractError::NoPurchases {}
+            );
+            append_audit_entry(deps.storage, env.block.time, info.sender.as_str(), "draw")?;
+            DrawRng::new(seed, &env)
+        }
+    };
+
+    let entries: Vec<(String, Vec<Deposit>)> = DEPOSITS
+        .range(deps.storage, None, None, Order::Ascending)
+        .take(limit)
+        .collect::<StdResult<_>>()?;
+    let number_of_drawn_tickets: u32 = entries
+        .iter()
+        .flat_map(|(_, deposits)| deposits)
+        .map(|deposit| deposit.number_of_tickets)
+        .sum();
+    let mut token_ids = AVAILABLE_TOKENS
+        .keys(deps.storage, None, None, Order::Ascending)
+        .take(number_of_drawn_tickets as usize)
+        .collect::<StdResult<Vec<String>>>()?
+        .into_iter();
+
+    let mut number_of_tickets = NUMBER_OF_TICKETS.load(deps.storage)?;
+    let mut current_number = NUMBER_OF_TOKENS_AVAILABLE.load(deps.storage)?;
+    let mut number_of_winners = 0;
+    let mut refund_msgs: Vec<CosmosMsg> = vec![];
+    for (depositor, deposits) in entries {
+        let mut purchases = PURCHASES
+            .may_load(deps.storage, &depositor)?
+            .unwrap_or_default();
+        let number_of_purchases = purchases.len();
+        let mut refund = Uint128::zero();
+        for deposit in deposits.iter() {
+            for _ in 0..deposit.number_of_tickets {
+                let is_drawn =
+                    Uint128::from(rng.next_u64() % u64::from(number_of_tickets)) < current_number;
+                number_of_tickets -= 1;
+                let token_id = if is_drawn { token_ids.next() } else { None };
+                let Some(token_id) = token_id else {
+                    refund = refund.checked_add(get_ticket_cost(&state, deposit)?)?;
+                    continue;
+                };
+
+                purchases.push(Purchase {
+                    token_id: token_id.clone(),
+                    price: state.price.clone(),
+                    tax_amount: deposit.tax_amount,
+                    amount_to_send: deposit.amount_to_send,
+                    tip_amount: Uint128::zero(),
+                    msgs: deposit.msgs.clone(),
+                    purchaser: depositor.clone(),
+                    purchased_at: env.block.time,
+                });
+                TOKEN_PURCHASERS.save(deps.storage, &token_id, &depositor)?;
+
+                state.amount_to_send = state.amount_to_send.checked_add(deposit.amount_to_send)?;
+                state.amount_received = state
+                    .amount_received
+                    .checked_add(get_ticket_cost(&state, deposit)?)?;
+                add_revenue(
+                    deps.storage,
+                    &Coin::new(deposit.amount_to_send.u128(), state.price.denom.clone()),
+                )?;
+                state.amount_sold = state.amount_sold.checked_add(Uint128::one())?;
+
+                remove_available_token(deps.storage, &token_id)?;
+                current_number = current_number.checked_sub(Uint128::one())?;
+                number_of_winners += 1;
+            }
+        }
+        if purchases.len() > number_of_purchases {
+            if number_of_purchases == 0 {
+                state.number_of_purchasers += 1;
+            }
+            PURCHASES.save(deps.storage, &depositor, &purchases)?;
+        }
+        DEPOSITS.remove(deps.storage, &depositor);
+
+        if !refund.is_zero() {
+            let to_address = REFUND_ADDRESSES
+                .may_load(deps.storage, &depositor)?
+                .unwrap_or(depositor);
+            refund_msgs.push(CosmosMsg::Bank(BankMsg::Send {
+                to_address,
+                amount: coins(refund.u128(), state.price.denom.clone()),
+            }));
+        }
+    }
+    NUMBER_OF_TICKETS.save(deps.storage, &number_of_tickets)?;
+    NUMBER_OF_TOKENS_AVAILABLE.save(deps.storage, &current_number)?;
+    STATE.save(deps.storage, &state)?;
+    if DEPOSITS.is_empty(deps.storage) {
+        DRAW_RNG.remove(deps.storage);
+    } else {
+        DRAW_RNG.save(deps.storage, &rng.0)?;
+    }
+
+    Ok(Response::new()
+        .add_attribute("action", "draw")
+        .add_attribute("number_of_winners", number_of_winners.to_string())
+        .add_attribute("number_of_refunds", refund_msgs.len().to_string())
+        .add_messages(refund_msgs))
+}
+
+/// Pseudo-random sequence (splitmix64) seeded from the owner provided seed and the block. As
+/// block data is known to validators ahead of time, this is only suitable for low stakes draws.
+struct DrawRng(u64);
+
+impl DrawRng {
+    fn new(seed: u64, env: &Env) -> Self {
+        Self(seed ^ env.block.height.rotate_left(32) ^ env.block.time.nanos())
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+}
+
+fn purchase_tokens(
+    deps: &mut DepsMut,
+    env: &Env,
+    token_ids: Vec<String>,
+    info: &MessageInfo,
+    purchaser: &str,
+    state: &mut State,
+    purchases: &mut Vec<Purchase>,
+) -> Result<Coin, ContractError> {
+    // CHECK :: There are any tokens left to purchase.
+    ensure!(!token_ids.is_empty(), ContractError::AllTokensPurchased {});
+
+    // The rate is only queried once, so that every token of the purchase costs the same.
+    if let Some(oracle) = &state.oracle {
+        state.price.amount = query_oracle_price(&deps.querier, oracle, &state.price.denom)?;
+    }
+
+    let number_of_tokens_purchased = token_ids.len();
+    if purchases.is_empty() {
+        state.number_of_purchasers += 1;
+    }
+
+    // CHECK :: The user has sent enough funds to cover the base fee (without any taxes on top).
+    let total_cost = Coin::new(
+        state.price.amount.u128() * number_of_tokens_purchased as u128,
+        state.price.denom.clone(),
+    );
+    ensure!(
+        has_coins(&info.funds, &total_cost),
+        ContractError::InsufficientFunds {}
+    );
+
+    let mut total_tax_amount = Uint128::zero();
+
+    // This is the same for each token, so we only need to do it once.
+    let (msgs, _events, remainder) = ADOContract::default().on_funds_transfer(
+        &deps.as_ref(),
+        info.sender.to_string(),
+        Funds::Native(state.price.clone()),
+        encode_binary(&"")?,
+    )?;
+
+    let mut current_number = NUMBER_OF_TOKENS_AVAILABLE.load(deps.storage)?;
+    for token_id in token_ids {
+        let remaining_amount = remainder.try_get_coin()?;
+
+        let tax_amount = get_tax_amount(&msgs, state.price.amount, remaining_amount.amount);
+        let (tax_amount, amount_to_send) = get_token_payment(
+            state.price.amount,
+            tax_amount,
+            remaining_amount.amount,
+            state.tax_inclusive,
+        )?;
+        let rate_msgs = if state.tax_inclusive {
+            scale_rate_messages(&msgs, state.price.amount.checked_sub(amount_to_send)?)?
+        } else {
+            msgs.clone()
+        };
+
+        let purchase = Purchase {
+            token_id: token_id.clone(),
+            price: state.price.clone(),
+            tax_amount,
+            amount_to_send,
+            tip_amount: Uint128::zero(),
+            msgs: rate_msgs,
+            purchaser: purchaser.to_string(),
+            purchased_at: env.block.time,
+        };
+        total_tax_amount = total_tax_amount.checked_add(tax_amount)?;
+
+        state.amount_to_send = state.amount_to_send.checked_add(amount_to_send)?;
+        add_revenue(
+            deps.storage,
+            &Coin::new(amount_to_send.u128(), remaining_amount.denom),
+        )?;
+        state.amount_sold = state.amount_sold.checked_add(Uint128::one())?;
+
+        purchases.push(purchase);
+
+        remove_available_token(deps.storage, &token_id)?;
+        remove_reservation(deps.storage, &token_id)?;
+        TOKEN_PURCHASERS.save(deps.storage, &token_id, &purchaser.to_string())?;
+        current_number = current_number.checked_sub(Uint128::one())?;
+    }
+    NUMBER_OF_TOKENS_AVAILABLE.save(deps.storage, &current_number)?;
+    extend_sniped_sale(state, env);
+
+    // CHECK :: User has sent enough to cover taxes.
+    let required_payment = Coin {
+        denom: state.price.denom.clone(),
+        amount: state
+            .price
+            .amount
+            .checked_mul(Uint128::from(number_of_tokens_purchased as u128))?
+            .checked_add(total_tax_amount)?,
+    };
+    ensure!(
+        has_coins(&info.funds, &required_payment),
+        ContractError::InsufficientFunds {}
+    );
+    state.amount_received = state.amount_received.checked_add(required_payment.amount)?;
+    Ok(required_payment)
+}
+
+/// Converts the USD price per token of an oracle priced sale to an amount of `denom`, rounding up.
+fn query_oracle_price(
+    querier: &QuerierWrapper,
+    oracle: &OraclePrice,
+    denom: &str,
+) -> Result<Uint128, ContractError> {
+    let res: OracleRateResponse = querier.query_wasm_smart(
+        oracle.address.clone(),
+        &OracleQueryMsg::Rate {
+            denom: denom.to_string(),
+        },
+    )?;
+    oracle
+        .usd_price
+        .checked_div_ceil(res.rate)
+        .map_err(|_| ContractError::CustomError {
+            msg: format!("Invalid oracle rate {} for {denom}", res.rate),
+        })
+}
+
+/// Pushes the end of the sale out when a purchase is made within the sale's anti-snipe window,
+/// as long as the sale hasn't been extended by its maximum yet.
+fn extend_sniped_sale(state: &mut State, env: &Env) {
+    let Some(anti_snipe) = state.anti_snipe.as_mut() else {
+        return;
+    };
+    let Expiration::AtTime(end_time) = state.end_time else {
+        return;
+    };
+    let remaining_ms =
+        (end_time.nanos() / 1_000_000).saturating_sub(env.block.time.nanos() / 1_000_000);
+    if remaining_ms > anti_snipe.window_ms {
+        return;
+    }
+    let extension_ms = cmp::min(
+        anti_snipe.extension_ms,
+        anti_snipe
+            .max_extension_ms
+            .saturating_sub(anti_snipe.extended_ms),
+    );
+    anti_snipe.extended_ms += extension_ms;
+    state.end_time = Expiration::AtTime(end_time.plus_nanos(extension_ms * 1_000_000));
+}
+
+/// Splits the payment for a single token into the tax charged on top of the price and the amount
+/// owed to the recipient, given the `tax_amount` the rates charge on top of `price` and the
+/// `remaining_amount` of the price left after royalties.
+///
+/// For tax inclusive sales nothing is charged on top of the price. Instead the tax is the portion
+/// of the price that, with the same rates, would have been charged on top of the pre-tax price,
+/// i.e. `tax_amount * price / (price + tax_amount)`, rounded down.
+fn get_token_payment(
+    price: Uint128,
+    tax_amount: Uint128,
+    remaining_amount: Uint128,
+    tax_inclusive: bool,
+) -> Result<(Uint128, Uint128), ContractError> {
+    if !tax_inclusive || tax_amount.is_zero() {
+        return Ok((tax_amount, remaining_amount));
+    }
+    let included_tax = tax_amount.multiply_ratio(price, price.checked_add(tax_amount)?);
+    Ok((Uint128::zero(), remaining_amount.checked_sub(included_tax)?))
+}
+
+/// Scales the rates' payments for a single token of a tax inclusive sale down to `withheld`, the
+/// part of the price that doesn't go to the recipient, as the rates charge their tax on top of the
+/// price. The rounding remainder goes to the last payment, so that exactly `withheld` is paid out.
+fn scale_rate_messages(msgs: &[SubMsg], withheld: Uint128) -> Result<Vec<SubMsg>, ContractError> {
+    let payments: Vec<Uint128> = msgs
+        .iter()
+        .filter_map(|msg| match &msg.msg {
+            CosmosMsg::Bank(BankMsg::Send { amount, .. }) => Some(amount[0].amount),
+            _ => None,
+        })
+        .collect();
+    let total = payments
+        .iter()
+        .try_fold(Uint128::zero(), |total, amount| total.checked_add(*amount))?;
+    if total.is_zero() {
+        return Ok(msgs.to_vec());
+    }
+
+    let mut remaining = withheld;
+    let mut number_of_payments_left = payments.len();
+    let mut scaled = vec![];
+    for mut msg in msgs.iter().cloned() {
+        if let CosmosMsg::Bank(BankMsg::Send { amount, .. }) = &mut msg.msg {
+            number_of_payments_left -= 1;
+            let share = if number_of_payments_left == 0 {
+                remaining
+            } else {
+                amount[0].amount.multiply_ratio(withheld, total)
+            };
+            remaining = remaining.checked_sub(share)?;
+            // A payment rounded down to nothing can't be sent.
+            if share.is_zero() {
+                continue;
+            }
+            amount[0].amount = share;
+        }
+        scaled.push(msg);
+    }
+    Ok(scaled)
+}
+
+fn execute_claim_refund(ctx: ExecuteContext) -> Result<Response, ContractError> {
+    let ExecuteContext {
+        deps, info, env, ..
+    } = ctx;
+    nonpayable(&info)?;
+
+    let state = STATE.may_load(deps.storage)?;
+    ensure!(state.is_some(), ContractError::NoOngoingSale {});
+    let state = state.unwrap();
+    ensure!(
+        state.end_time.is_expired(&env.block),
+        ContractError::SaleNotEnded {}
+    );
+    ensure!(is_refunding(&state), ContractError::MinSalesExceeded {});
+
+    let purchases = PURCHASES.may_load(deps.storage, info.sender.as_str())?;
+    ensure!(purchases.is_some(), ContractError::NoPurchases {});
+    let purchases = purchases.unwrap();
+    let refund_msg = process_refund(deps.storage, &purchases)?;
+    let mut resp = Response::new();
+    if let Some(refund_msg) = refund_msg {
+        resp = resp.add_submessage(refund_msg);
+    }
+
+    Ok(resp.add_attribute("action", "claim_refund"))
+}
+
+fn execute_set_refund_address(
+    ctx: ExecuteContext,
+    address: String,
+) -> Result<Response, ContractError> {
+    let ExecuteContext { deps, info, .. } = ctx;
+    nonpayable(&info)?;
+
+    let address = deps.api.addr_validate(&address)?;
+    REFUND_ADDRESSES.save(deps.storage, info.sender.as_str(), &address.to_string())?;
+
+    Ok(Response::new()
+        .add_attribute("action", "set_refund_address")
+        .add_attribute("address", address))
+}
+
+fn execute_refund_all(
+    ctx: ExecuteContext,
+    start_after: Option<String>,
+    limit: Option<u32>,
+) -> Result<Response, ContractError> {
+    let ExecuteContext {
+        deps, info, env, ..
+    } = ctx;
+    nonpayable(&info)?;
+
+    let state = STATE.may_load(deps.storage)?;
+    ensure!(state.is_some(), ContractError::NoOngoingSale {});
+    let state = state.unwrap();
+    ensure!(
+        state.end_time.is_expired(&env.block),
+        ContractError::SaleNotEnded {}
+    );
+    ensure!(is_refunding(&state), ContractError::MinSalesExceeded {});
+
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    ensure!(limit > 0, ContractError::LimitMustNotBeZero {});
+    let (refund_msgs, number_of_purchasers) = issue_refunds(deps.storage, start_after, limit)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "refund_all")
+        .add_attribute("number_of_purchasers", number_of_purchasers.to_string())
+        .add_submessages(refund_msgs))
+}
+fn end_condition_met(state: &State, env: &Env) -> bool {
+    // Check if the sale has reached its end time
+    let is_sale_expired = state.end_time.is_expired(&env.block);
+
+    // Check if the minimum tokens sold condition is met
+    let is_minimum_sold = is_minimum_met(state);
+
+    // Check if the target percentage of tokens sold condition is met
+    let is_target_percentage_sold = match state.target_percentage_sold {
+        Some(target_percentage) if !state.total_tokens.is_zero() => {
+            let sold_percentage = state.amount_sold.u128() * 100 / state.total_tokens.u128();
+            sold_percentage >= target_percentage
+        }
+        _ => false, // No target percentage set, so this condition is always false
+    };
+
+    // Check if the maximum duration condition is met
+    let is_max_duration_reached = match state.max_duration {
+        Some(max_duration) => match state.start_time {
+            Expiration::AtTime(start_time) => {
+                let duration_elapsed = env
+                    .block
+                    .time
+                    .seconds()
+                    .saturating_sub(start_time.seconds());
+                duration_elapsed >= max_duration
+            }
+            // Sales always start at a time, see `execute_start_sale`.
+            _ => false,
+        },
+        None => false, // No maximum duration set, so this condition is always false
+    };
+
+    // Check if the owner has manually ended the sale
+    let is_owner_ended = state.owner_ended;
+
+    // The end condition is met if any of the conditions are true
+    is_sale_expired
+        || is_minimum_sold
+        || is_target_percentage_sold
+        || is_max_duration_reached
+        || is_owner_ended
+}
+
+fn execute_end_sale(ctx: ExecuteContext, limit: Option<u32>) -> Result<Response, ContractError> {
+    let ExecuteContext {
+        mut deps,
+        info,
+        env,
+        ..
+    } = ctx;
+    nonpayable(&info)?;
+
+    let mut state = STATE.load(deps.storage)?;
+    let number_of_tokens_available = NUMBER_OF_TOKENS_AVAILABLE.load(deps.storage)?;
+
+    // CHECK :: Lottery deposits have been drawn before the sale can be completed.
+    ensure!(
+        state.sale_mode != SaleMode::Lottery || DEPOSITS.is_empty(deps.storage),
+        ContractError::SaleNotEnded {}
+    );
+
+    let is_owner = ADOContract::default().is_contract_owner(deps.storage, info.sender.as_str())?;
+
+    if end_condition_met(&state, &env)
+        || state.end_time.is_expired(&env.block)
+        || number_of_tokens_available.is_zero()
+        || is_owner
+    {
+        // Ending the sale before its end time stops any further purchases, which would otherwise
+        // change the purchases while they are being completed.
+        if !state.end_time.is_expired(&env.block) {
+            state.end_time = Expiration::AtTime(env.block.time);
+            STATE.save(deps.storage, &state)?;
+        }
+        // Proceed with sale completion steps. Only a sale that sold the minimum pays out, a sale
+        // that ended without doing so, e.g. by selling out fewer tokens than the minimum, refunds.
+        if !is_refunding(&state) {
+            transfer_tokens_and_send_funds(&mut deps, info.clone(), env, limit)
+        } else {
+            issue_refunds_and_burn_tokens(&mut deps, env, limit)
+        }
+    } else {
+        // Continue with the sale until the end condition is met or the owner decides to end it
+        Ok(Response::default())
+    }
+}
+fn execute_abort_sale(ctx: ExecuteContext, limit: Option<u32>) -> Result<Response, ContractError> {
+    let ExecuteContext {
+        mut deps,
+        info,
+        env,
+        ..
+    } = ctx;
+    nonpayable(&info)?;
+
+    ensure!(
+        ADOContract::default().is_contract_owner(deps.storage, info.sender.as_str())?,
+        ContractError::Unauthorized {}
+    );
+    let state = STATE.may_load(deps.storage)?;
+    ensure!(state.is_some(), ContractError::NoOngoingSale {});
+    let mut state = state.unwrap();
+
+    // The first call aborts the sale, later calls continue refunding.
+    if !state.aborted {
+        // CHECK :: Lottery deposits have been drawn, so that they are accounted as purchases.
+        ensure!(
+            state.sale_mode != SaleMode::Lottery || DEPOSITS.is_empty(deps.storage),
+            ContractError::SaleNotEnded {}
+        );
+        // CHECK :: The sale's tokens are not being transferred after it ended successfully.
+        ensure!(
+            state.amount_transferred.is_zero(),
+            ContractError::CustomError {
+                msg: "Tokens have already been transferred".to_string(),
+            }
+        );
+        // CHECK :: Nothing has been paid out during the sale, otherwise it can't be refunded.
+        ensure!(
+            PENDING_PAYOUTS.is_empty(deps.storage),
+            ContractError::CustomError {
+                msg: "Funds have already been paid out during the sale".to_string(),
+            }
+        );
+        state.aborted = true;
+        // Ending the sale now stops any further purchases.
+        state.end_time = Expiration::AtTime(env.block.time);
+        STATE.save(deps.storage, &state)?;
+        append_audit_entry(
+            deps.storage,
+            env.block.time,
+            info.sender.as_str(),
+            "abort_sale",
+        )?;
+    }
+
+    issue_refunds_and_burn_tokens(&mut deps, env, limit)
+}
+
+fn execute_decline_purchase(
+    ctx: ExecuteContext,
+    token_id: String,
+) -> Result<Response, ContractError> {
+    let ExecuteContext {
+        deps, info, env, ..
+    } = ctx;
+    nonpayable(&info)?;
+
+    let state = STATE.may_load(deps.storage)?;
+    ensure!(state.is_some(), ContractError::NoOngoingSale {});
+    let mut state = state.unwrap();
+    // CHECK :: The sale has ended successfully, but no tokens have been transferred yet.
+    ensure!(
+        state.end_time.is_expired(&env.block),
+        ContractError::SaleNotEnded {}
+    );
+    ensure!(
+        !is_refunding(&state),
+        ContractError::UnsupportedOperation {}
+    );
+    ensure!(
+        state.amount_transferred.is_zero(),
+        ContractError::CustomError {
+            msg: "Tokens have already been transferred".to_string(),
+        }
+    );
+    // CHECK :: The declined purchase's funds are still held by the contract.
+    ensure!(
+        PENDING_PAYOUTS.is_empty(deps.storage),
+        ContractError::CustomError {
+            msg: "Funds have already been paid out during the sale".to_string(),
+        }
+    );
+
+    let purchaser = info.sender.to_string();
+    let mut purchases = PURCHASES
+        .may_load(deps.storage, &purchaser)?
+        .unwrap_or_default();
+    let index = purchases
+        .iter()
+        .position(|purchase| purchase.token_id == token_id);
+    ensure!(index.is_some(), ContractError::NoPurchases {});
+    let purchase = purchases.remove(index.unwrap());
+    let credited = Coin::new(
+        purchase
+            .amount_to_send
+            .checked_add(purchase.tip_amount)?
+            .u128(),
+        purchase.price.denom.clone(),
+    );
+
+    state.amount_sold = state.amount_sold.checked_sub(Uint128::one())?;
+    state.amount_to_send = state.amount_to_send.checked_sub(credited.amount)?;
+    if purchases.is_empty() {
+        state.number_of_purchasers = state.number_of_purchasers.saturating_sub(1);
+    }
+    // CHECK :: Declining doesn't turn the sale into a refunding one for everyone else.
+    ensure!(
+        is_minimum_met(&state),
+        ContractError::CustomError {
+            msg: "Declining would take the sale below its minimum".to_string(),
+        }
+    );
+    subtract_revenue(deps.storage, &credited)?;
+    STATE.save(deps.storage, &state)?;
+    add_available_token(deps.storage, &token_id)?;
+    let current_number = NUMBER_OF_TOKENS_AVAILABLE.load(deps.storage)?;
+    NUMBER_OF_TOKENS_AVAILABLE.save(deps.storage, &current_number.checked_add(Uint128::one())?)?;
+
+    // Refunding removes all of the purchaser's purchases, so the remaining ones are saved after.
+    let refund_msg = process_refund(deps.storage, &[purchase])?;
+    if !purchases.is_empty() {
+        PURCHASES.save(deps.storage, &purchaser, &purchases)?;
+    }
+    let mut resp = Response::new();
+    if let Some(refund_msg) = refund_msg {
+        resp = resp.add_submessage(refund_msg);
+    }
+
+    Ok(resp
+        .add_attribute("action", "decline_purchase")
+        .add_attribute("token_id", token_id))
+}
+
+/// Undoes the decline of `purchase` after its refund failed, buying the token back.
+fn restore_declined_purchase(
+    storage: &mut dyn Storage,
+    state: &mut State,
+    purchase: &Purchase,
+) -> Result<(), ContractError> {
+    let credited = purchase.amount_to_send.checked_add(purchase.tip_amount)?;
+    state.amount_sold = state.amount_sold.checked_add(Uint128::one())?;
+    state.amount_to_send = state.amount_to_send.checked_add(credited)?;
+    add_revenue(
+        storage,
+        &Coin::new(credited.u128(), purchase.price.denom.clone()),
+    )?;
+
+    remove_available_token(storage, &purchase.token_id)?;
+    let current_number = NUMBER_OF_TOKENS_AVAILABLE.load(storage)?;
+    NUMBER_OF_TOKENS_AVAILABLE.save(storage, &current_number.checked_sub(Uint128::one())?)?;
+    Ok(())
+}
+
+/// Whether the sale refunds its purchasers instead of paying out, because it was aborted or
+/// because its minimum was not met.
+fn is_refunding(state: &State) -> bool {
+    state.aborted || !is_minimum_met(state)
+}
+
+/// Whether enough tokens have been sold to enough distinct purchasers for the sale to go through.
+fn is_minimum_met(state: &State) -> bool {
+    state.amount_sold >= state.min_tokens_sold
+        && state
+            .min_purchasers
+            .is_none_or(|min_purchasers| state.number_of_purchasers >= min_purchasers)
+}
+
+fn issue_refunds_and_burn_tokens(
+    deps: &mut DepsMut,
+    env: Env,
+    limit: Option<u32>,
+) -> Result<Response, ContractError> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    ensure!(limit > 0, ContractError::LimitMustNotBeZero {});
+    // Issue refunds for `limit` number of users.
+    let (refund_msgs, number_of_purchasers) = issue_refunds(deps.storage, None, limit)?;
+
+    // Burn `limit` number of tokens
+    let burn_msgs = get_burn_messages(deps, env.contract.address.to_string(), limit)?;
+
+    if burn_msgs.is_empty() && number_of_purchasers == 0 {
+        // When all tokens have been burned and all purchases have been refunded, the sale is over.
+        clear_state(deps.storage)?;
+    }
+
+    Ok(Response::new()
+        .add_attribute("action", "issue_refunds_and_burn_tokens")
+        .add_submessages(refund_msgs)
+        .add_submessages(burn_msgs))
+}
+
+/// Refunds up to `limit` purchasers after `start_after`, returning the refund messages and the
+/// number of purchasers refunded.
+fn issue_refunds(
+    storage: &mut dyn Storage,
+    start_after: Option<String>,
+    limit: usize,
+) -> Result<(Vec<SubMsg>, usize), ContractError> {
+    let start = start_after.as_deref().map(Bound::exclusive);
+    let purchases: Vec<Vec<Purchase>> = PURCHASES
+        .range(storage, start, None, Order::Ascending)
+        .take(limit)
         .flatten()
         .map(|(_v, p)| p)
         .collect();
+
+    let mut refund_msgs: Vec<SubMsg> = vec![];
     for purchase_vec in purchases.iter() {
-        let refund_msg = process_refund(deps.storage, purchase_vec, &state.price);
+        let refund_msg = process_refund(storage, purchase_vec)?;
         if let Some(refund_msg) = refund_msg {
             refund_msgs.push(refund_msg);
         }
     }
+    Ok((refund_msgs, purchases.len()))
+}
+
+fn transfer_tokens_and_send_funds(
+    deps: &mut DepsMut,
+    info: MessageInfo,
+    env: Env,
+    limit: Option<u32>,
+) -> Result<Response, ContractError> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    ensure!(limit > 0, ContractError::LimitMustNotBeZero {});
+    let mut state = STATE.load(deps.storage)?;
+    let mut resp = Response::new();
+
+    // Send the funds if they haven't been sent yet and if all of the tokens have been transferred.
+    if state.amount_transferred == state.amount_sold {
+        let revenue = take_revenue(deps.storage)?;
+        let is_paying_out = !revenue.is_empty();
+        if is_paying_out {
+            track_pending_payouts(deps.storage, &revenue)?;
+
+            // Send funds to the recipient
+            let payout_msgs =
+                get_payout_messages(&deps.as_ref(), &info, &env, &state.recipient, revenue)?;
+            resp = resp.add_submessages(payout_msgs);
+
+            state.amount_to_send = Uint128::zero();
+            STATE.save(deps.storage, &state)?;
+        }
+
+        // Once all purchased tokens have been transferred, begin burning `limit` number of tokens
+        // that were not purchased.
+        let burn_msgs = get_burn_messages(deps, env.contract.address.to_string(), limit)?;
+
+        if burn_msgs.is_empty() {
+            // When burn messages are empty, we have finished the sale, which is represented by
+            // having no State. This waits for the payouts to go through, as a failed payout needs
+            // the State to be retried.
+            if !is_paying_out {
+                clear_state(deps.storage)?;
+            }
+        } else {
+            resp = resp.add_submessages(burn_msgs);
+        }
+    } else {
+        // Continue transferring the next `limit` tokens to purchasers. Each purchase is removed
+        // once its token is transferred, so the next call continues where this one stopped.
+        let mut transfer_msgs: Vec<SubMsg> = vec![];
+        // The rates are paid for each token as it is transferred.
+        let mut rate_msgs: Vec<SubMsg> = vec![];
+
+        // Every purchaser has at least one purchase, so no more than `limit` are needed.
+        let entries: Vec<(String, Vec<Purchase>)> = PURCHASES
+            .range(deps.storage, None, None, Order::Ascending)
+            .take(limit)
+            .collect::<StdResult<_>>()?;
+
+        for (purchaser, mut purchases) in entries {
+            let number_to_transfer = cmp::min(limit - transfer_msgs.len(), purchases.len());
+            if number_to_transfer == 0 {
+                break;
+            }
+            let transferred: Vec<Purchase> = purchases.drain(..number_to_transfer).collect();
+            if purchases.is_empty() {
+                PURCHASES.remove(deps.storage, &purchaser);
+            } else {
+                PURCHASES.save(deps.storage, &purchaser, &purchases)?;
+            }
+
+            for purchase in transferred {
+                transfer_msgs.push(SubMsg::reply_on_error(
+                    WasmMsg::Execute {
+                        contract_addr: state.token_address.clone(),
+                        msg: encode_binary(&Cw721ExecuteMsg::TransferNft {
+                            recipient: AndrAddr::from_string(purchase.purchaser.clone()),
+                            token_id: purchase.token_id.clone(),
+                        })?,
+                        funds: vec![],
+                    },
+                    TRANSFER_REPLY_ID,
+                ));
+                rate_msgs.extend(purchase.msgs);
+
+                // Update state
+                state.amount_transferred += Uint128::one();
+            }
+        }
+
+        STATE.save(deps.storage, &state)?;
+
+        resp = resp
+            .add_submessages(transfer_msgs)
+            .add_submessages(merge_sub_msgs(rate_msgs));
+    }
+
+    Ok(resp.add_attribute("action", "transfer_tokens_and_send_funds"))
+}
+/// Keeps track of what each payout of `revenue` sends in case it fails and has to be retried.
+fn track_pending_payouts(storage: &mut dyn Storage, revenue: &[Coin]) -> Result<(), ContractError> {
+    PENDING_PAYOUTS.clear(storage);
+    for (i, funds) in revenue.iter().enumerate() {
+        PENDING_PAYOUTS.save(storage, PAYOUT_REPLY_ID + i as u64, funds)?;
+    }
+    Ok(())
+}
+
+/// Generates the messages sending the sale's revenue to `recipient`, with one payout per denom.
+/// The n-th payout replies on error with `PAYOUT_REPLY_ID + n`.
+fn get_payout_messages(
+    deps: &Deps,
+    info: &MessageInfo,
+    env: &Env,
+    recipient: &Recipient,
+    revenue: Vec<Coin>,
+) -> Result<Vec<SubMsg>, ContractError> {
+    let mut payout_msgs = vec![];
+    for (i, funds) in revenue.into_iter().enumerate() {
+        let reply_id = PAYOUT_REPLY_ID + i as u64;
+        match recipient.msg {
+            None => {
+                let mut payout_msg = recipient.generate_direct_msg(deps, vec![funds])?;
+                payout_msg.id = reply_id;
+                payout_msg.reply_on = ReplyOn::Error;
+                payout_msgs.push(payout_msg);
+            }
+            Some(_) => {
+                let amp_message = recipient.generate_amp_msg(deps, Some(vec![funds.clone()]))?;
+                let pkt = AMPPkt::new(
+                    info.sender.clone(),
+                    env.contract.address.clone(),
+                    vec![amp_message],
+                );
+                let kernel_address = ADOContract::default().get_kernel_address(deps.storage)?;
+                let mut payout_msg = pkt.to_sub_msg(kernel_address, Some(vec![funds]), reply_id)?;
+                payout_msg.reply_on = ReplyOn::Error;
+                payout_msgs.push(payout_msg);
+            }
+        }
+    }
+    Ok(payout_msgs)
+}
+
+/// Adds `refund` to what the sale has refunded, ensuring that its refunds never add up to more
+/// than the funds it received. Only the refunds in the denom of `price` are checked, as the sale
+/// only tracks what it received in that denom.
+fn record_refund(storage: &mut dyn Storage, refund: &[Coin]) -> Result<(), ContractError> {
+    let mut state = STATE.load(storage)?;
+    let Some(refund) = refund.iter().find(|coin| coin.denom == state.price.denom) else {
+        return Ok(());
+    };
+    state.amount_refunded = state.amount_refunded.checked_add(refund.amount)?;
+
+    // CHECK :: We never refund more than what was received.
+    ensure!(
+        state.amount_refunded <= state.amount_received,
+        ContractError::InsufficientFunds {}
+    );
+    STATE.save(storage, &state)?;
+    Ok(())
+}
+
+/// Returns the total amount a purchaser is owed for the given purchases, which is the price of
+/// each token plus the tax paid on it, with one coin per denom the tokens were priced in.
+fn get_refund_amount(purchases: &[Purchase]) -> Result<Vec<Coin>, ContractError> {
+    let mut amounts: BTreeMap<&str, Uint128> = BTreeMap::new();
+    for p in purchases {
+        // This represents the total amount of funds they sent for each purchase.
+        let amount = amounts.entry(p.price.denom.as_str()).or_default();
+        *amount = amount
+            .checked_add(p.price.amount)?
+            .checked_add(p.tax_amount)?
+            .checked_add(p.tip_amount)?;
+    }
+    Ok(amounts
+        .into_iter()
+        .filter(|(_, amount)| !amount.is_zero())
+        .map(|(denom, amount)| Coin::new(amount.u128(), denom))
+        .collect())
+}
+
+/// Processes a vector of purchases for the SAME user by merging all funds into a single BankMsg,
+/// with one coin per denom, sent to the purchaser's refund address if they set one. The given
+/// purchaser is then removed from `PURCHASES`. The purchases
+/// are kept aside until the sale is cleared, so that a failed refund can restore them for a
+/// retry, while a successful refund cannot be claimed again.
+///
+/// ## Arguments
+/// * `storage`  - Mutable reference to Storage
+/// * `purchase` - Vector of purchases for the same user to issue a refund message for.
+///
+/// Returns an `Option<SubMsg>` which is `None` when the amount to refund is zero.
+fn process_refund(
+    storage: &mut dyn Storage,
+    purchases: &[Purchase],
+) -> Result<Option<SubMsg>, ContractError> {
+    let purchaser = purchases[0].purchaser.clone();
+    // Remove each entry as they get processed.
+    PURCHASES.remove(storage, &purchaser);
+    for purchase in purchases {
+        TOKEN_PURCHASERS.remove(storage, &purchase.token_id);
+    }
+    // Reduce a user's purchases into one message. While the tax paid on each item should
+    // be the same, it is not guaranteed given that the rates module is mutable during the
+    // sale.
+    let amount = get_refund_amount(purchases)?;
+
+    if amount.is_empty() {
+        return Ok(None);
+    }
+    record_refund(storage, &amount)?;
+    let reply_id = PENDING_REFUNDS
+        .keys(storage, None, None, Order::Descending)
+        .next()
+        .transpose()?
+        .map_or(REFUND_REPLY_ID, |last_id| last_id + 1);
+    PENDING_REFUNDS.save(storage, reply_id, &purchases.to_vec())?;
+
+    let to_address = REFUND_ADDRESSES
+        .may_load(storage, &purchaser)?
+        .unwrap_or(purchaser);
+    Ok(Some(SubMsg::reply_on_error(
+        CosmosMsg::Bank(BankMsg::Send { to_address, amount }),
+        reply_id,
+    )))
+}
+
+fn get_burn_messages(
+    deps: &mut DepsMut,
+    address: String,
+    limit: usize,
+) -> Result<Vec<SubMsg>, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    let token_address = config.token_address.get_raw_address(&deps.as_ref())?;
+    let tokens_to_burn = query_tokens(&deps.querier, token_address.to_string(), address, limit)?;
+
+    tokens_to_burn
+        .into_iter()
+        .map(|token_id| {
+            // Any token that is burnable has been added to this map, and so must be removed.
+            remove_available_token(deps.storage, &token_id)?;
+            Ok(SubMsg::reply_on_error(
+                WasmMsg::Execute {
+                    contract_addr: token_address.to_string(),
+                    funds: vec![],
+                    msg: encode_binary(&Cw721ExecuteMsg::Burn { token_id })?,
+                },
+                BURN_REPLY_ID,
+            ))
+        })
+        .collect()
+}
+
+fn clear_state(storage: &mut dyn Storage) -> Result<(), ContractError> {
+    STATE.remove(storage);
+    REVENUE.clear(storage);
+    PENDING_PAYOUTS.clear(storage);
+    PENDING_REFUNDS.clear(storage);
+    RESERVATIONS.clear(storage);
+    RESERVER_TOKENS.clear(storage);
+    PURCHASES.clear(storage);
+    TOKEN_PURCHASERS.clear(storage);
+    NUMBER_OF_TOKENS_AVAILABLE.save(storage, &Uint128::zero())?;
+
+    Ok(())
+}
+
+fn query_tokens(
+    querier: &QuerierWrapper,
+    token_address: String,
+    owner: String,
+    limit: usize,
+) -> Result<Vec<String>, ContractError> {
+    let res: TokensResponse = querier.query(&QueryRequest::Wasm(WasmQuery::Smart {
+        contract_addr: token_address,
+        msg: encode_binary(&Cw721QueryMsg::Tokens {
+            owner,
+            start_after: None,
+            limit: Some(limit as u32),
+        })?,
+    }))?;
+    Ok(res.tokens)
+}
+
+fn query_owner_of(
+    querier: &QuerierWrapper,
+    token_address: String,
+    token_id: String,
+) -> Result<String, ContractError> {
+    let res: OwnerOfResponse = querier.query(&QueryRequest::Wasm(WasmQuery::Smart {
+        contract_addr: token_address,
+        msg: encode_binary(&Cw721QueryMsg::OwnerOf {
+            token_id,
+            include_expired: None,
+        })?,
+    }))?;
+    Ok(res.owner)
+}
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn query(deps: Deps, env: Env, msg: QueryMsg) -> Result<Binary, ContractError> {
+    let res = match msg {
+        QueryMsg::State {} => encode_binary(&query_state(deps)?)?,
+        QueryMsg::SaleDenom {} => encode_binary(&query_sale_denom(deps)?)?,
+        QueryMsg::PayoutDestination {} => encode_binary(&query_payout_destination(deps)?)?,
+        QueryMsg::PriceDisplay {} => encode_binary(&query_price_display(deps)?)?,
+        QueryMsg::ProjectedOutcome {} => encode_binary(&query_projected_outcome(deps, env)?)?,
+        QueryMsg::ProgressBps {} => encode_binary(&query_progress_bps(deps)?)?,
+        QueryMsg::EscrowStatus {} => encode_binary(&query_escrow_status(deps, env)?)?,
+        QueryMsg::Config {} => encode_binary(&query_config(deps)?)?,
+        QueryMsg::ResolvedConfig {} => encode_binary(&query_resolved_config(deps)?)?,
+        QueryMsg::AvailableTokens {
+            start_after,
+            limit,
+            verify,
+            order,
+        } => encode_binary(&query_available_tokens(
+            deps,
+            env,
+            start_after,
+            limit,
+            verify.unwrap_or(false),
+            order.unwrap_or_default(),
+        )?)?,
+        QueryMsg::IsTokenAvailable { id } => encode_binary(&query_is_token_available(deps, id))?,
+        QueryMsg::TokenStatus { id } => encode_binary(&query_token_status(deps, id)?)?,
+        QueryMsg::IsSoldOut {} => encode_binary(&query_is_sold_out(deps)?)?,
+        QueryMsg::CanMint {} => encode_binary(&query_can_mint(deps)?)?,
+        QueryMsg::RemainingAllowance { purchaser } => {
+            encode_binary(&query_remaining_allowance(deps, env, purchaser)?)?
+        }
+        QueryMsg::Purchases { purchaser } => encode_binary(&query_purchases(deps, purchaser)?)?,
+        QueryMsg::PurchaseTaxes { purchaser } => {
+            encode_binary(&query_purchase_taxes(deps, purchaser)?)?
+        }
+        QueryMsg::RefundBreakdown { purchaser } => {
+            encode_binary(&query_refund_breakdown(deps, purchaser)?)?
+        }
+        QueryMsg::AuditLog { start_after, limit } => {
+            encode_binary(&get_audit_log(deps.storage, start_after, limit)?)?
+        }
+        _ => ADOContract::default().query(deps, env, msg)?,
+    };
+    Ok(res)
+}
+
+fn query_state(deps: Deps) -> Result<Option<State>, ContractError> {
+    Ok(STATE.may_load(deps.storage)?)
+}
+
+fn query_sale_denom(deps: Deps) -> Result<Option<String>, ContractError> {
+    Ok(STATE.may_load(deps.storage)?.map(|state| state.price.denom))
+}
+
+fn query_payout_destination(
+    deps: Deps,
+) -> Result<Option<PayoutDestinationResponse>, ContractError> {
+    let Some(state) = STATE.may_load(deps.storage)? else {
+        return Ok(None);
+    };
+    // The address is resolved as `get_payout_messages` does when it pays out.
+    Ok(Some(PayoutDestinationResponse {
+        address: state.recipient.address.get_raw_address(&deps)?,
+        is_amp: state.recipient.msg.is_some(),
+    }))
+}
+
+fn query_price_display(deps: Deps) -> Result<Option<PriceDisplayResponse>, ContractError> {
+    Ok(STATE
+        .may_load(deps.storage)?
+        .map(|state| PriceDisplayResponse {
+            denom: state.price.denom.clone(),
+            price: state.price,
+        }))
+}
+
+fn query_projected_outcome(
+    deps: Deps,
+    env: Env,
+) -> Result<Option<ProjectedOutcome>, ContractError> {
+    let Some(state) = STATE.may_load(deps.storage)? else {
+        return Ok(None);
+    };
+    if is_minimum_met(&state) {
+        return Ok(Some(ProjectedOutcome::WillSucceed));
+    }
+    let number_of_tokens_available = NUMBER_OF_TOKENS_AVAILABLE.load(deps.storage)?;
+    // Every new purchaser buys at least one of the available tokens.
+    let can_still_reach_purchasers = state.min_purchasers.is_none_or(|min_purchasers| {
+        Uint128::from(state.number_of_purchasers) + number_of_tokens_available
+            >= Uint128::from(min_purchasers)
+    });
+    let can_still_sell_minimum = !state.end_time.is_expired(&env.block)
+        && state.amount_sold.checked_add(number_of_tokens_available)? >= state.min_tokens_sold
+        && can_still_reach_purchasers;
+    if can_still_sell_minimum {
+        Ok(Some(ProjectedOutcome::Undetermined))
+    } else {
+        Ok(Some(ProjectedOutcome::WillRefund))
+    }
+}
+
+fn query_progress_bps(deps: Deps) -> Result<Option<ProgressBpsResponse>, ContractError> {
+    let Some(state) = STATE.may_load(deps.storage)? else {
+        return Ok(None);
+    };
+    // `multiply_ratio` can't overflow, as it multiplies with a wider integer.
+    let bps = |total: Uint128| -> Result<u64, ContractError> {
+        if total.is_zero() {
+            return Ok(MAX_BPS);
+        }
+        let bps = state.amount_sold.multiply_ratio(MAX_BPS, total);
+        u64::try_from(bps.u128())
+            .map_err(|_| ContractError::Std(StdError::generic_err("bps overflow")))
+    };
+    // A sale never sells more than its tokens.
+    let sold_bps = bps(state.total_tokens)?;
+    validate_bps("sold_bps", sold_bps)?;
+    Ok(Some(ProgressBpsResponse {
+        sold_bps,
+        min_bps: cmp::min(bps(state.min_tokens_sold)?, MAX_BPS),
+    }))
+}
+
+fn query_escrow_status(
+    deps: Deps,
+    env: Env,
+) -> Result<Option<EscrowStatusResponse>, ContractError> {
+    let Some(state) = STATE.may_load(deps.storage)? else {
+        return Ok(None);
+    };
+    let balance = deps
+        .querier
+        .query_balance(env.contract.address, state.price.denom.clone())?;
+
+    let mut pending_refunds = Uint128::zero();
+    for item in PURCHASES.range(deps.storage, None, None, Order::Ascending) {
+        let (_, purchases) = item?;
+        for refund in get_refund_amount(&purchases)? {
+            if refund.denom == state.price.denom {
+                pending_refunds = pending_refunds.checked_add(refund.amount)?;
+            }
+        }
+    }
+
+    // Refunds are only owed while the sale can still fail. Afterwards the purchases may well
+    // exceed the balance, as funds can have been paid out during the sale.
+    let obligations = if is_minimum_met(&state) {
+        state.amount_to_send
+    } else {
+        cmp::max(state.amount_to_send, pending_refunds)
+    };
+    Ok(Some(EscrowStatusResponse {
+        shortfall: obligations.saturating_sub(balance.amount),
+        balance,
+        amount_to_send: state.amount_to_send,
+        pending_refunds,
+    }))
+}
+
+fn query_config(deps: Deps) -> Result<Config, ContractError> {
+    Ok(CONFIG.load(deps.storage)?)
+}
+
+fn query_resolved_config(deps: Deps) -> Result<ResolvedConfigResponse, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    Ok(ResolvedConfigResponse {
+        token_address: config.token_address.get_raw_address(&deps)?,
+        can_mint_after_sale: config.can_mint_after_sale,
+    })
+}
+
+fn query_available_tokens(
+    deps: Deps,
+    env: Env,
+    start_after: Option<String>,
+    limit: Option<u32>,
+    verify: bool,
+    order: TokenOrder,
+) -> Result<Vec<String>, ContractError> {
+    let token_ids = get_available_tokens(deps.storage, start_after, limit, &order)?;
+    if !verify {
+        return Ok(token_ids);
+    }
+
+    // `AVAILABLE_TOKENS` can drift from the actual owners if tokens were transferred out.
+    let token_address = CONFIG
+        .load(deps.storage)?
+        .token_address
+        .get_raw_address(&deps)?;
+    let crowdfund_contract = env.contract.address.to_string();
+    let mut verified = vec![];
+    for token_id in token_ids {
+        // A token that was burned has no owner, so the query fails.
+        let owner = query_owner_of(&deps.querier, token_address.to_string(), token_id.clone());
+        if owner.is_ok_and(|owner| owner == crowdfund_contract) {
+            verified.push(token_id);
+        }
+    }
+    Ok(verified)
+}
+
+fn query_is_token_available(deps: Deps, id: String) -> bool {
+    AVAILABLE_TOKENS.has(deps.storage, &id)
+}
+
+fn query_token_status(deps: Deps, id: String) -> Result<TokenStatus, ContractError> {
+    if AVAILABLE_TOKENS.has(deps.storage, &id) {
+        return Ok(TokenStatus::Available);
+    }
+    Ok(match TOKEN_PURCHASERS.may_load(deps.storage, &id)? {
+        Some(purchaser) => TokenStatus::Sold { purchaser },
+        None => TokenStatus::Unknown,
+    })
+}
+
+fn query_is_sold_out(deps: Deps) -> Result<bool, ContractError> {
+    if STATE.may_load(deps.storage)?.is_none() {
+        return Ok(false);
+    }
+    Ok(NUMBER_OF_TOKENS_AVAILABLE.load(deps.storage)?.is_zero())
+}
+
+fn query_can_mint(deps: Deps) -> Result<bool, ContractError> {
+    // These are the same conditions `execute_mint` checks.
+    if STATE.may_load(deps.storage)?.is_some() {
+        return Ok(false);
+    }
+    let sale_conducted = SALE_CONDUCTED.load(deps.storage)?;
+    Ok(CONFIG.load(deps.storage)?.can_mint_after_sale || !sale_conducted)
+}
+
+fn query_refund_breakdown(deps: Deps, purchaser: String) -> Result<Vec<Coin>, ContractError> {
+    let purchases = PURCHASES
+        .may_load(deps.storage, &purchaser)?
+        .unwrap_or_default();
+    get_refund_amount(&purchases)
+}
+
+fn query_remaining_allowance(
+    deps: Deps,
+    env: Env,
+    purchaser: String,
+) -> Result<RemainingAllowanceResponse, ContractError> {
+    let Some(state) = STATE.may_load(deps.storage)? else {
+        return Ok(RemainingAllowanceResponse {
+            remaining: 0,
+            can_purchase: false,
+        });
+    };
+    // Mirrors the `max_possible` computed when purchasing or depositing.
+    let used = match state.sale_mode {
+        SaleMode::FirstComeFirstServed => PURCHASES
+            .may_load(deps.storage, &purchaser)?
+            .map_or(0, |purchases| purchases.len() as u32),
+        SaleMode::Lottery => get_number_of_tickets(deps.storage, &purchaser)?,
+    };
+    let remaining = state.max_amount_per_wallet.saturating_sub(used);
+    let number_of_tokens_available = NUMBER_OF_TOKENS_AVAILABLE.load(deps.storage)?;
+
+    Ok(RemainingAllowanceResponse {
+        remaining,
+        can_purchase: remaining > 0
+            && !state.end_time.is_expired(&env.block)
+            && !number_of_tokens_available.is_zero(),
+    })
+}
+
+fn query_purchases(deps: Deps, purchaser: String) -> Result<Vec<Purchase>, ContractError> {
+    Ok(PURCHASES
+        .may_load(deps.storage, &purchaser)?
+        .unwrap_or_default())
+}
+
+fn query_purchase_taxes(
+    deps: Deps,
+    purchaser: String,
+) -> Result<Vec<PurchaseTaxResponse>, ContractError> {
+    Ok(query_purchases(deps, purchaser)?
+        .into_iter()
+        .map(|purchase| PurchaseTaxResponse {
+            token_id: purchase.token_id,
+            tax_amount: purchase.tax_amount,
+        })
+        .collect())
+}
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn migrate(mut deps: DepsMut, env: Env, _msg: MigrateMsg) -> Result<Response, ContractError> {
+    let stored_version = cw2::get_contract_version(deps.storage)?.version;
+    let resp = ADOContract::default().migrate(deps.branch(), CONTRACT_NAME, CONTRACT_VERSION)?;
+
+    // Only an actual upgrade can find data stored in a previous layout.
+    if stored_version != CONTRACT_VERSION {
+        migrate_legacy_config(deps.storage)?;
+        migrate_token_sequences(deps.storage)?;
+        migrate_legacy_sale(deps, &env)?;
+    }
+    Ok(resp)
+}
+
+/// Rewrites a config stored in the previous layout, filling the fields it didn't have with their
+/// defaults.
+fn migrate_legacy_config(storage: &mut dyn Storage) -> Result<(), ContractError> {
+    let Ok(legacy_config) = LEGACY_CONFIG.load(storage) else {
+        return Ok(());
+    };
+    CONFIG.save(
+        storage,
+        &Config {
+            token_address: legacy_config.token_address,
+            can_mint_after_sale: legacy_config.can_mint_after_sale,
+            max_mints_per_block: None,
+            max_reservation_ttl_ms: None,
+        },
+    )?;
+    Ok(())
+}
+
+/// Rewrites a sale that was ongoing during the upgrade into the current layout, filling the
+/// fields it didn't have with their defaults. Data already in the current layout is left as is.
+/// Gives the tokens that were made available before their order was tracked a sequence, in
+/// lexical order.
+fn migrate_token_sequences(storage: &mut dyn Storage) -> Result<(), ContractError> {
+    let token_ids: Vec<String> = AVAILABLE_TOKENS
+        .keys(storage, None, None, Order::Ascending)
+        .collect::<StdResult<_>>()?;
+    for token_id in token_ids {
+        if !TOKEN_SEQUENCES.has(storage, &token_id) {
+            add_available_token(storage, &token_id)?;
+        }
+    }
+    Ok(())
+}
+
+fn migrate_legacy_sale(deps: DepsMut, env: &Env) -> Result<(), ContractError> {
+    // A state in the current layout doesn't parse as the legacy one, and the purchases of a sale
+    // are in the same layout as its state.
+    let Ok(Some(legacy_state)) = LEGACY_STATE.may_load(deps.storage) else {
+        return Ok(());
+    };
+    let number_of_tokens_available = NUMBER_OF_TOKENS_AVAILABLE.load(deps.storage)?;
+    let token_address = CONFIG
+        .load(deps.storage)?
+        .token_address
+        .get_raw_address(&deps.as_ref())?;
+    let legacy_purchases: Vec<(String, Vec<LegacyPurchase>)> = LEGACY_PURCHASES
+        .range(deps.storage, None, None, Order::Ascending)
+        .collect::<StdResult<_>>()?;
+    let total_tax = legacy_purchases
+        .iter()
+        .flat_map(|(_, purchases)| purchases)
+        .try_fold(Uint128::zero(), |accum, purchase| {
+            accum.checked_add(purchase.tax_amount)
+        })?;
+    let amount_received = legacy_state
+        .price
+        .amount
+        .checked_mul(legacy_state.amount_sold)?
+        .checked_add(total_tax)?;
+    STATE.save(
+        deps.storage,
+        &State {
+            // When the sale started was not recorded, so it is taken to be the upgrade.
+            start_time: Expiration::AtTime(env.block.time),
+            end_time: legacy_state.end_time,
+            price: legacy_state.price.clone(),
+            min_tokens_sold: legacy_state.min_tokens_sold,
+            max_amount_per_wallet: legacy_state.max_amount_per_wallet,
+            amount_sold: legacy_state.amount_sold,
+            amount_to_send: legacy_state.amount_to_send,
+            amount_transferred: legacy_state.amount_transferred,
+            recipient: legacy_state.recipient,
+            total_tokens: legacy_state
+                .amount_sold
+                .checked_add(number_of_tokens_available)?,
+            target_percentage_sold: None,
+            max_duration: None,
+            owner_ended: false,
+            token_address: token_address.to_string(),
+            sale_mode: SaleMode::default(),
+            tax_inclusive: false,
+            min_purchase: None,
+            payout_threshold: None,
+            anti_snipe: None,
+            oracle: None,
+            aborted: false,
+            min_purchasers: None,
+            number_of_purchasers: legacy_purchases.len() as u32,
+            refund_overpayment: true,
+            enforce_distinct_tokens: false,
+            amount_received,
+            amount_refunded: Uint128::zero(),
+        },
+    )?;
+    // The legacy sale only ever received its price denom.
+    add_revenue(
+        deps.storage,
+        &Coin::new(
+            legacy_state.amount_to_send.u128(),
+            legacy_state.price.denom.clone(),
+        ),
+    )?;
+
+    for (purchaser, purchases) in legacy_purchases {
+        let purchases: Vec<Purchase> = purchases
+            .into_iter()
+            .map(|purchase| Purchase {
+                token_id: purchase.token_id,
+                price: legacy_state.price.clone(),
+                tax_amount: purchase.tax_amount,
+                amount_to_send: legacy_state.price.amount,
+                tip_amount: Uint128::zero(),
+                msgs: purchase.msgs,
+                purchaser: purchase.purchaser,
+                purchased_at: env.block.time,
+            })
+            .collect();
+        for purchase in purchases.iter() {
+            TOKEN_PURCHASERS.save(deps.storage, &purchase.token_id, &purchaser)?;
+        }
+        PURCHASES.save(deps.storage, &purchaser, &purchases)?;
+    }
+    Ok(())
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::{AuditEntry, LegacyConfig, LegacyState, NEXT_TOKEN_SEQUENCE};
+    use andromeda_non_fungible_tokens::cw721::TokenExtension;
+    use andromeda_std::ado_base::{
+        hooks::{AndromedaHook, HookMsg, OnFundsTransferResponse},
+        modules::Module,
+    };
+    use andromeda_std::amp::recipient::Recipient;
+    use cosmwasm_std::testing::{
+        mock_dependencies, mock_env, mock_info, MockApi, MockQuerier, MockStorage,
+        MOCK_CONTRACT_ADDR,
+    };
+    use cosmwasm_std::{
+        attr, coin, from_json, to_json_binary, Addr, ContractResult, Decimal, OwnedDeps, Response,
+        SystemResult,
+    };
+    use cw_utils::Expiration;
+
+    // Test cases for the end_condition_met function
+    #[test]
+    fn test_end_condition_met_expired() {
+        let env = mock_env();
+        let state = State {
+            end_time: Expiration::AtTime(env.block.time),
+            min_tokens_sold: Uint128::new(100),
+            amount_sold: Uint128::new(50),
+            total_tokens: Uint128::new(200),
+            ..mock_state()
+        };
+        assert!(end_condition_met(&state, &env));
+    }
+
+    #[test]
+    fn test_end_condition_met_minimum_sold() {
+        let state = State {
+            min_tokens_sold: Uint128::new(100),
+            amount_sold: Uint128::new(150),
+            total_tokens: Uint128::new(200),
+            ..mock_state()
+        };
+        assert!(end_condition_met(&state, &mock_env()));
+    }
+
+    #[test]
+    fn test_end_condition_met_target_percentage() {
+        let state = State {
+            min_tokens_sold: Uint128::new(200),
+            amount_sold: Uint128::new(110),
+            total_tokens: Uint128::new(200),
+            target_percentage_sold: Some(50),
+            ..mock_state()
+        };
+        assert!(end_condition_met(&state, &mock_env()));
+    }
+
+    #[test]
+    fn test_end_condition_met_max_duration() {
+        let env = mock_env();
+        let state = State {
+            start_time: Expiration::AtTime(env.block.time.minus_seconds(50)),
+            min_tokens_sold: Uint128::new(200),
+            total_tokens: Uint128::new(200),
+            max_duration: Some(50),
+            ..mock_state()
+        };
+        assert!(end_condition_met(&state, &env));
+    }
+
+    #[test]
+    fn test_end_condition_met_owner_ended() {
+        let state = State {
+            min_tokens_sold: Uint128::new(200),
+            total_tokens: Uint128::new(200),
+            owner_ended: true,
+            ..mock_state()
+        };
+        assert!(end_condition_met(&state, &mock_env()));
+    }
+
+    #[test]
+    fn test_end_condition_met_not_met() {
+        let env = mock_env();
+        let state = State {
+            start_time: Expiration::AtTime(env.block.time.minus_seconds(10)),
+            min_tokens_sold: Uint128::new(100),
+            amount_sold: Uint128::new(50),
+            total_tokens: Uint128::new(200),
+            target_percentage_sold: Some(50),
+            max_duration: Some(50),
+            ..mock_state()
+        };
+        assert!(!end_condition_met(&state, &env));
+    }
+
+    fn mock_purchase(token_id: &str, purchaser: &str, tax_amount: u128) -> Purchase {
+        Purchase {
+            token_id: token_id.to_string(),
+            price: coin(100, "uusd"),
+            tax_amount: Uint128::new(tax_amount),
+            amount_to_send: Uint128::new(100),
+            tip_amount: Uint128::zero(),
+            msgs: vec![],
+            purchaser: purchaser.to_string(),
+            purchased_at: mock_env().block.time,
+        }
+    }
+
+    fn mock_state() -> State {
+        State {
+            start_time: Expiration::AtHeight(0),
+            end_time: Expiration::Never {},
+            price: coin(100, "uusd"),
+            min_tokens_sold: Uint128::new(1),
+            max_amount_per_wallet: 5,
+            amount_sold: Uint128::zero(),
+            amount_to_send: Uint128::zero(),
+            amount_transferred: Uint128::zero(),
+            recipient: Recipient::from_string("recipient"),
+            total_tokens: Uint128::new(2),
+            target_percentage_sold: None,
+            max_duration: None,
+            owner_ended: false,
+            token_address: "token_contract".to_string(),
+            sale_mode: SaleMode::FirstComeFirstServed,
+            tax_inclusive: false,
+            min_purchase: None,
+            payout_threshold: None,
+            anti_snipe: None,
+            oracle: None,
+            aborted: false,
+            min_purchasers: None,
+            number_of_purchasers: 0,
+            refund_overpayment: true,
+            enforce_distinct_tokens: false,
+            amount_received: Uint128::zero(),
+            amount_refunded: Uint128::zero(),
+        }
+    }
+
+    /// Mocks the CW721 contract, with `owned_tokens` being owned by the crowdfund contract and
+    /// any other token by "someone".
+    fn mock_cw721_owners(
+        deps: &mut OwnedDeps<MockStorage, MockApi, MockQuerier>,
+        owned_tokens: &'static [&'static str],
+    ) {
+        CONFIG
+            .save(
+                deps.as_mut().storage,
+                &Config {
+                    token_address: AndrAddr::from_string("token_contract"),
+                    can_mint_after_sale: true,
+                    max_mints_per_block: None,
+                    max_reservation_ttl_ms: None,
+                },
+            )
+            .unwrap();
+        deps.querier.update_wasm(move |query| match query {
+            WasmQuery::Smart { msg, .. } => match from_json(msg).unwrap() {
+                Cw721QueryMsg::OwnerOf { token_id, .. } => {
+                    let owner = if owned_tokens.contains(&token_id.as_str()) {
+                        MOCK_CONTRACT_ADDR
+                    } else {
+                        "someone"
+                    };
+                    SystemResult::Ok(ContractResult::Ok(
+                        to_json_binary(&OwnerOfResponse {
+                            owner: owner.to_string(),
+                            approvals: vec![],
+                        })
+                        .unwrap(),
+                    ))
+                }
+                Cw721QueryMsg::Tokens { owner, .. } => {
+                    let tokens = if owner == MOCK_CONTRACT_ADDR {
+                        owned_tokens
+                            .iter()
+                            .map(|token_id| token_id.to_string())
+                            .collect()
+                    } else {
+                        vec![]
+                    };
+                    SystemResult::Ok(ContractResult::Ok(
+                        to_json_binary(&TokensResponse { tokens }).unwrap(),
+                    ))
+                }
+                Cw721QueryMsg::ContractInfo {} => SystemResult::Ok(ContractResult::Ok(
+                    to_json_binary(&ContractInfoResponse {
+                        name: "Test Tokens".to_string(),
+                        symbol: "TT".to_string(),
+                    })
+                    .unwrap(),
+                )),
+                _ => panic!("unexpected cw721 query"),
+            },
+            // The kernel's registry, which resolves the VFS address.
+            WasmQuery::Raw { .. } => SystemResult::Ok(ContractResult::Ok(
+                to_json_binary(&Addr::unchecked("vfs")).unwrap(),
+            )),
+            _ => panic!("unexpected query"),
+        });
+    }
+
+    fn start_sale(deps: DepsMut, min_tokens_sold: u128) -> Result<Response, ContractError> {
+        let env = mock_env();
+        execute_start_sale(
+            ExecuteContext::new(deps, mock_info("owner", &[]), env.clone()),
+            None,
+            MillisecondsExpiration::from_seconds(env.block.time.seconds() + 100),
+            coin(100, "uusd"),
+            Uint128::new(min_tokens_sold),
+            Some(5),
+            Recipient::from_string("recipient"),
+            SaleOptions::default(),
+        )
+    }
+
+    fn init_ado(deps: DepsMut) {
+        ADOContract::default()
+            .instantiate(
+                deps.storage,
+                mock_env(),
+                deps.api,
+                &deps.querier,
+                mock_info("owner", &[]),
+                BaseInstantiateMsg {
+                    ado_type: CONTRACT_NAME.to_string(),
+                    ado_version: CONTRACT_VERSION.to_string(),
+                    kernel_address: "kernel".to_string(),
+                    owner: None,
+                },
+            )
+            .unwrap();
+    }
+
+    /// Registers a rates module charging two taxes of 5% each on top of the price.
+    fn mock_rates(deps: &mut OwnedDeps<MockStorage, MockApi, MockQuerier>) {
+        ADOContract::default()
+            .register_modules(
+                "owner",
+                deps.as_mut().storage,
+                Some(vec![Module::new("rates", "rates", false)]),
+            )
+            .unwrap();
+        deps.querier.update_wasm(|query| match query {
+            WasmQuery::Smart { contract_addr, msg } if contract_addr == "rates" => {
+                let HookMsg::AndrHook(AndromedaHook::OnFundsTransfer { amount, .. }) =
+                    from_json(msg).unwrap()
+                else {
+                    panic!("unexpected hook");
+                };
+                let tax = amount
+                    .try_get_coin()
+                    .unwrap()
+                    .amount
+                    .multiply_ratio(5u128, 100u128);
+                let msgs = ["tax1", "tax2"]
+                    .into_iter()
+                    .map(|to_address| {
+                        SubMsg::new(BankMsg::Send {
+                            to_address: to_address.to_string(),
+                            amount: coins(tax.u128(), "uusd"),
+                        })
+                    })
+                    .collect();
+                SystemResult::Ok(ContractResult::Ok(
+                    to_json_binary(&Some(OnFundsTransferResponse {
+                        msgs,
+                        events: vec![],
+                        leftover_funds: amount,
+                    }))
+                    .unwrap(),
+                ))
+            }
+            WasmQuery::Raw { .. } => SystemResult::Ok(ContractResult::Ok(
+                to_json_binary(&Addr::unchecked("vfs")).unwrap(),
+            )),
+            _ => panic!("unexpected query"),
+        });
+    }
+
+    fn mint_available(storage: &mut dyn Storage, token_ids: &[&str]) {
+        for token_id in token_ids {
+            add_available_token(storage, token_id).unwrap();
+        }
+        NUMBER_OF_TOKENS_AVAILABLE
+            .save(storage, &Uint128::from(token_ids.len() as u128))
+            .unwrap();
+    }
+
+    #[test]
+    fn test_record_refund() {
+        let mut deps = mock_dependencies();
+        STATE
+            .save(
+                deps.as_mut().storage,
+                &State {
+                    amount_received: Uint128::new(300),
+                    ..mock_state()
+                },
+            )
+            .unwrap();
+
+        // Refunds in other denoms are not tracked.
+        record_refund(
+            deps.as_mut().storage,
+            &[coin(100, "uusd"), coin(500, "uandr")],
+        )
+        .unwrap();
+        record_refund(deps.as_mut().storage, &coins(200, "uusd")).unwrap();
+        assert_eq!(
+            Uint128::new(300),
+            STATE.load(deps.as_ref().storage).unwrap().amount_refunded
+        );
+    }
+
+    #[test]
+    fn test_record_refund_corrupted_state() {
+        let mut deps = mock_dependencies();
+        STATE
+            .save(
+                deps.as_mut().storage,
+                &State {
+                    amount_received: Uint128::new(200),
+                    ..mock_state()
+                },
+            )
+            .unwrap();
+
+        // Each batch is covered on its own, but together they refund more than was received.
+        record_refund(deps.as_mut().storage, &coins(100, "uusd")).unwrap();
+        record_refund(deps.as_mut().storage, &coins(100, "uusd")).unwrap();
+        let err = record_refund(deps.as_mut().storage, &coins(100, "uusd")).unwrap_err();
+        assert_eq!(ContractError::InsufficientFunds {}, err);
+    }
+
+    #[test]
+    fn test_purchases_record_timestamps() {
+        let mut deps = mock_dependencies();
+        init_ado(deps.as_mut());
+        mock_cw721_owners(&mut deps, &[]);
+        NUMBER_OF_TOKENS_AVAILABLE
+            .save(deps.as_mut().storage, &Uint128::new(2))
+            .unwrap();
+        let mut state = mock_state();
+        let mut purchases = vec![];
+        let info = mock_info("A", &coins(100, "uusd"));
+
+        let mut env = mock_env();
+        purchase_tokens(
+            &mut deps.as_mut(),
+            &env,
+            vec!["token1".to_string()],
+            &info,
+            "A",
+            &mut state,
+            &mut purchases,
+        )
+        .unwrap();
+
+        env.block.time = env.block.time.plus_seconds(5);
+        purchase_tokens(
+            &mut deps.as_mut(),
+            &env,
+            vec!["token2".to_string()],
+            &info,
+            "A",
+            &mut state,
+            &mut purchases,
+        )
+        .unwrap();
+        PURCHASES
+            .save(deps.as_mut().storage, "A", &purchases)
+            .unwrap();
+
+        let res = query(
+            deps.as_ref(),
+            env.clone(),
+            QueryMsg::Purchases {
+                purchaser: "A".to_string(),
+            },
+        )
+        .unwrap();
+        let purchases: Vec<Purchase> = from_json(res).unwrap();
+        assert_eq!(2, purchases.len());
+        assert!(purchases[0].purchased_at <= purchases[1].purchased_at);
+        assert_eq!(env.block.time, purchases[1].purchased_at);
+    }
+
+    #[test]
+    fn test_lottery_draw() {
+        let mut deps = mock_dependencies();
+        init_ado(deps.as_mut());
+        mock_cw721_owners(&mut deps, &[]);
+        mint_available(deps.as_mut().storage, &["token1", "token2"]);
+        let mut env = mock_env();
+        STATE
+            .save(
+                deps.as_mut().storage,
+                &State {
+                    end_time: Expiration::AtTime(env.block.time.plus_seconds(100)),
+                    sale_mode: SaleMode::Lottery,
+                    ..mock_state()
+                },
+            )
+            .unwrap();
+
+        let deposits = [("A", 2u32), ("B", 1), ("C", 1)];
+        for (depositor, n) in deposits {
+            let info = mock_info(depositor, &coins(100 * n as u128, "uusd"));
+            execute_deposit(
+                ExecuteContext::new(deps.as_mut(), info, env.clone()),
+                Some(n),
+            )
+            .unwrap();
+        }
+
+        // Purchasing directly is not possible in a lottery sale.
+        let info = mock_info("D", &coins(100, "uusd"));
+        let err = execute_purchase(
+            ExecuteContext::new(deps.as_mut(), info, env.clone()),
+            None,
+            None,
+        )
+        .unwrap_err();
+        assert_eq!(ContractError::UnsupportedOperation {}, err);
+
+        env.block.time = env.block.time.plus_seconds(101);
+        let draw = |deps: DepsMut, sender: &str, limit: u32| {
+            execute_draw(
+                ExecuteContext::new(deps, mock_info(sender, &[]), env.clone()),
+                42,
+                Some(limit),
+            )
+        };
+        // Only the owner can start the draw, which anyone can then continue.
+        assert_eq!(
+            ContractError::Unauthorized {},
+            draw(deps.as_mut(), "anyone", 2).unwrap_err()
+        );
+        let mut messages = draw(deps.as_mut(), "owner", 2).unwrap().messages;
+        assert!(DRAW_RNG.exists(deps.as_ref().storage));
+        messages.extend(draw(deps.as_mut(), "anyone", 2).unwrap().messages);
+        assert!(!DRAW_RNG.exists(deps.as_ref().storage));
+
+        // Exactly the available tokens are allocated.
+        let state = STATE.load(deps.as_ref().storage).unwrap();
+        assert_eq!(Uint128::new(2), state.amount_sold);
+        assert_eq!(Uint128::new(200), state.amount_to_send);
+        assert!(AVAILABLE_TOKENS.is_empty(deps.as_ref().storage));
+        assert!(DEPOSITS.is_empty(deps.as_ref().storage));
+        assert_eq!(0, NUMBER_OF_TICKETS.load(deps.as_ref().storage).unwrap());
+
+        // Every ticket that was not drawn is refunded.
+        let mut won = 0;
+        for (depositor, n) in deposits {
+            let purchases = PURCHASES
+                .may_load(deps.as_ref().storage, depositor)
+                .unwrap()
+                .unwrap_or_default();
+            let refunded: u128 = messages
+                .iter()
+                .filter_map(|msg| match &msg.msg {
+                    CosmosMsg::Bank(BankMsg::Send { to_address, amount })
+                        if to_address == depositor =>
+                    {
+                        Some(amount[0].amount.u128())
+                    }
+                    _ => None,
+                })
+                .sum();
+            assert_eq!(100 * n as u128, 100 * purchases.len() as u128 + refunded);
+            won += purchases.len();
+        }
+        assert_eq!(2, won);
+    }
+
+    #[test]
+    fn test_lottery_applies_rates() {
+        let mut deps = mock_dependencies();
+        init_ado(deps.as_mut());
+        mock_rates(&mut deps);
+        mint_available(deps.as_mut().storage, &["token1"]);
+        let mut env = mock_env();
+        STATE
+            .save(
+                deps.as_mut().storage,
+                &State {
+                    end_time: Expiration::AtTime(env.block.time.plus_seconds(100)),
+                    sale_mode: SaleMode::Lottery,
+                    ..mock_state()
+                },
+            )
+            .unwrap();
+        let deposit = |deps: DepsMut, env: Env, amount: u128| {
+            execute_deposit(
+                ExecuteContext::new(deps, mock_info("A", &coins(amount, "uusd")), env),
+                Some(1),
+            )
+        };
+
+        // Each ticket costs the price and the tax on it.
+        assert_eq!(
+            ContractError::InsufficientFunds {},
+            deposit(deps.as_mut(), env.clone(), 100).unwrap_err()
+        );
+        deposit(deps.as_mut(), env.clone(), 110).unwrap();
+
+        env.block.time = env.block.time.plus_seconds(101);
+        execute_draw(
+            ExecuteContext::new(deps.as_mut(), mock_info("owner", &[]), env),
+            42,
+            None,
+        )
+        .unwrap();
+        let purchases = PURCHASES.load(deps.as_ref().storage, "A").unwrap();
+        assert_eq!(Uint128::new(10), purchases[0].tax_amount);
+        assert_eq!(2, purchases[0].msgs.len());
+        assert_eq!(
+            Uint128::new(110),
+            STATE.load(deps.as_ref().storage).unwrap().amount_received
+        );
+    }
+
+    #[test]
+    fn test_withdraw_deposit() {
+        let mut deps = mock_dependencies();
+        init_ado(deps.as_mut());
+        mock_cw721_owners(&mut deps, &[]);
+        mint_available(deps.as_mut().storage, &["token1"]);
+        let mut env = mock_env();
+        STATE
+            .save(
+                deps.as_mut().storage,
+                &State {
+                    end_time: Expiration::AtTime(env.block.time.plus_seconds(100)),
+                    sale_mode: SaleMode::Lottery,
+                    ..mock_state()
+                },
+            )
+            .unwrap();
+        for depositor in ["A", "B"] {
+            execute_deposit(
+                ExecuteContext::new(
+                    deps.as_mut(),
+                    mock_info(depositor, &coins(200, "uusd")),
+                    env.clone(),
+                ),
+                Some(2),
+            )
+            .unwrap();
+        }
+        let withdraw = |deps: DepsMut, env: Env, sender: &str| {
+            execute_withdraw_deposit(ExecuteContext::new(deps, mock_info(sender, &[]), env))
+        };
+        assert_eq!(
+            ContractError::SaleNotEnded {},
+            withdraw(deps.as_mut(), env.clone(), "A").unwrap_err()
+        );
+
+        // The owner never draws, so the depositors take their deposits back.
+        env.block.time = env.block.time.plus_seconds(101);
+        let res = withdraw(deps.as_mut(), env.clone(), "A").unwrap();
+        assert_eq!(
+            vec![SubMsg::new(BankMsg::Send {
+                to_address: "A".to_string(),
+                amount: coins(200, "uusd"),
+            })],
+            res.messages
+        );
+        assert_eq!(2, NUMBER_OF_TICKETS.load(deps.as_ref().storage).unwrap());
+        assert_eq!(
+            ContractError::NoPurchases {},
+            withdraw(deps.as_mut(), env.clone(), "A").unwrap_err()
+        );
+
+        // Once the draw has started, its deposits stay until they are drawn.
+        DRAW_RNG.save(deps.as_mut().storage, &0).unwrap();
+        assert_eq!(
+            ContractError::CustomError {
+                msg: "The draw has already started".to_string()
+            },
+            withdraw(deps.as_mut(), env, "B").unwrap_err()
+        );
+    }
+
+    #[test]
+    fn test_purchase_shortfall_refund() {
+        let mut deps = mock_dependencies();
+        init_ado(deps.as_mut());
+        mock_cw721_owners(&mut deps, &[]);
+        mint_available(deps.as_mut().storage, &["token1"]);
+        STATE.save(deps.as_mut().storage, &mock_state()).unwrap();
+
+        // Only a single token remains, but three are wanted.
+        let info = mock_info("A", &coins(300, "uusd"));
+        let res = execute_purchase(
+            ExecuteContext::new(deps.as_mut(), info, mock_env()),
+            Some(3),
+            None,
+        )
+        .unwrap();
+
+        assert_eq!(
+            vec![CosmosMsg::Bank(BankMsg::Send {
+                to_address: "A".to_string(),
+                amount: coins(200, "uusd"),
+            })],
+            res.messages.into_iter().map(|m| m.msg).collect::<Vec<_>>()
+        );
+        assert!(res
+            .attributes
+            .contains(&attr("number_of_tokens_purchased", "1")));
+        assert!(res.attributes.contains(&attr("shortfall", "2")));
+        assert_eq!(
+            vec![Event::new("purchase_shortfall")
+                .add_attribute("purchaser", "A")
+                .add_attribute("number_of_tokens_wanted", "3")
+                .add_attribute("number_of_tokens_purchased", "1")
+                .add_attribute("shortfall", "2")
+                .add_attribute("refund_amount", "200")],
+            res.events
+        );
+    }
+
+    #[test]
+    fn test_query_resolved_config() {
+        let mut deps = mock_dependencies();
+        init_ado(deps.as_mut());
+        CONFIG
+            .save(
+                deps.as_mut().storage,
+                &Config {
+                    token_address: AndrAddr::from_string("token_contract"),
+                    can_mint_after_sale: true,
+                    max_mints_per_block: None,
+                    max_reservation_ttl_ms: None,
+                },
+            )
+            .unwrap();
+        SALE_CONDUCTED.save(deps.as_mut().storage, &false).unwrap();
+        NUMBER_OF_TOKENS_AVAILABLE
+            .save(deps.as_mut().storage, &Uint128::zero())
+            .unwrap();
+
+        let res = query(deps.as_ref(), mock_env(), QueryMsg::ResolvedConfig {}).unwrap();
+        let config: ResolvedConfigResponse = from_json(res).unwrap();
+        assert!(config.can_mint_after_sale);
+
+        let mint_msg = CrowdfundMintMsg {
+            token_id: "token1".to_string(),
+            owner: None,
+            token_uri: None,
+            extension: TokenExtension {
+                publisher: "owner".to_string(),
+            },
+        };
+        let info = mock_info("owner", &[]);
+        let res = execute_mint(
+            ExecuteContext::new(deps.as_mut(), info, mock_env()),
+            vec![mint_msg],
+        )
+        .unwrap();
+        match &res.messages[0].msg {
+            CosmosMsg::Wasm(WasmMsg::Execute { contract_addr, .. }) => {
+                assert_eq!(config.token_address.as_str(), contract_addr)
+            }
+            msg => panic!("unexpected message {msg:?}"),
+        }
+    }
+
+    #[test]
+    fn test_payout_per_denom() {
+        let mut deps = mock_dependencies();
+        add_revenue(deps.as_mut().storage, &coin(100, "uusd")).unwrap();
+        add_revenue(deps.as_mut().storage, &coin(50, "uandr")).unwrap();
+        add_revenue(deps.as_mut().storage, &coin(100, "uusd")).unwrap();
+
+        let revenue = take_revenue(deps.as_mut().storage).unwrap();
+        assert_eq!(vec![coin(50, "uandr"), coin(200, "uusd")], revenue);
+        assert!(REVENUE.is_empty(deps.as_ref().storage));
+
+        let payout_msgs = get_payout_messages(
+            &deps.as_ref(),
+            &mock_info("anyone", &[]),
+            &mock_env(),
+            &Recipient::from_string("recipient"),
+            revenue,
+        )
+        .unwrap();
+        let payouts: Vec<CosmosMsg> = payout_msgs.into_iter().map(|m| m.msg).collect();
+        assert_eq!(
+            vec![
+                CosmosMsg::Bank(BankMsg::Send {
+                    to_address: "recipient".to_string(),
+                    amount: coins(50, "uandr"),
+                }),
+                CosmosMsg::Bank(BankMsg::Send {
+                    to_address: "recipient".to_string(),
+                    amount: coins(200, "uusd"),
+                }),
+            ],
+            payouts
+        );
+    }
+
+    #[test]
+    fn test_query_remaining_allowance() {
+        let mut deps = mock_dependencies();
+        mint_available(deps.as_mut().storage, &["token1", "token2", "token3"]);
+        STATE
+            .save(
+                deps.as_mut().storage,
+                &State {
+                    max_amount_per_wallet: 2,
+                    ..mock_state()
+                },
+            )
+            .unwrap();
+        PURCHASES
+            .save(
+                deps.as_mut().storage,
+                "partial",
+                &vec![mock_purchase("token4", "partial", 0)],
+            )
+            .unwrap();
+        PURCHASES
+            .save(
+                deps.as_mut().storage,
+                "maxed",
+                &vec![
+                    mock_purchase("token5", "maxed", 0),
+                    mock_purchase("token6", "maxed", 0),
+                ],
+            )
+            .unwrap();
+
+        for (purchaser, remaining, can_purchase) in [
+            ("fresh", 2, true),
+            ("partial", 1, true),
+            ("maxed", 0, false),
+        ] {
+            let res = query(
+                deps.as_ref(),
+                mock_env(),
+                QueryMsg::RemainingAllowance {
+                    purchaser: purchaser.to_string(),
+                },
+            )
+            .unwrap();
+            assert_eq!(
+                RemainingAllowanceResponse {
+                    remaining,
+                    can_purchase,
+                },
+                from_json(res).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn test_failed_payout_reply_restores_amount_to_send() {
+        let mut deps = mock_dependencies();
+        // The payout has already been sent out, marking the funds as sent.
+        STATE.save(deps.as_mut().storage, &mock_state()).unwrap();
+        PENDING_PAYOUTS
+            .save(deps.as_mut().storage, PAYOUT_REPLY_ID, &coin(200, "uusd"))
+            .unwrap();
+
+        let res = reply(
+            deps.as_mut(),
+            mock_env(),
+            Reply {
+                id: PAYOUT_REPLY_ID,
+                result: SubMsgResult::Err("payout failed".to_string()),
+            },
+        )
+        .unwrap();
+        assert!(res.attributes.contains(&attr("action", "payout_failed")));
+
+        let state = STATE.load(deps.as_ref().storage).unwrap();
+        assert_eq!(Uint128::new(200), state.amount_to_send);
+        assert_eq!(
+            vec![coin(200, "uusd")],
+            take_revenue(deps.as_mut().storage).unwrap()
+        );
+        assert!(PENDING_PAYOUTS.is_empty(deps.as_ref().storage));
+    }
+
+    #[test]
+    fn test_failed_mint_reply_reports_stage() {
+        let mut deps = mock_dependencies();
+        let err = reply(
+            deps.as_mut(),
+            mock_env(),
+            Reply {
+                id: MINT_REPLY_ID,
+                result: SubMsgResult::Err("token_id already claimed".to_string()),
+            },
+        )
+        .unwrap_err();
+        assert_eq!(
+            ContractError::Std(StdError::generic_err(
+                "mint failed: token_id already claimed"
+            )),
+            err
+        );
+    }
+
+    #[test]
+    fn test_seed_availability() {
+        let mut deps = mock_dependencies();
+        init_ado(deps.as_mut());
+        mock_cw721_owners(&mut deps, &["token1", "token2"]);
+        NUMBER_OF_TOKENS_AVAILABLE
+            .save(deps.as_mut().storage, &Uint128::zero())
+            .unwrap();
+
+        let info = mock_info("owner", &[]);
+        execute_seed_availability(
+            ExecuteContext::new(deps.as_mut(), info.clone(), mock_env()),
+            vec!["token1".to_string(), "token2".to_string()],
+        )
+        .unwrap();
+        assert!(AVAILABLE_TOKENS.has(deps.as_ref().storage, "token1"));
+        assert!(AVAILABLE_TOKENS.has(deps.as_ref().storage, "token2"));
+        assert_eq!(
+            Uint128::new(2),
+            NUMBER_OF_TOKENS_AVAILABLE
+                .load(deps.as_ref().storage)
+                .unwrap()
+        );
+
+        let err = execute_seed_availability(
+            ExecuteContext::new(deps.as_mut(), info, mock_env()),
+            vec!["token3".to_string()],
+        )
+        .unwrap_err();
+        assert_eq!(ContractError::TokenNotAvailable {}, err);
+        assert!(!AVAILABLE_TOKENS.has(deps.as_ref().storage, "token3"));
+    }
+
+    #[test]
+    fn test_query_available_tokens_verify() {
+        let mut deps = mock_dependencies();
+        // "token2" was transferred out of the contract without the crowdfund noticing.
+        mock_cw721_owners(&mut deps, &["token1"]);
+        mint_available(deps.as_mut().storage, &["token1", "token2"]);
+
+        for (verify, expected) in [
+            (Some(true), vec!["token1"]),
+            (Some(false), vec!["token1", "token2"]),
+            (None, vec!["token1", "token2"]),
+        ] {
+            let res = query(
+                deps.as_ref(),
+                mock_env(),
+                QueryMsg::AvailableTokens {
+                    start_after: None,
+                    limit: None,
+                    verify,
+                    order: None,
+                },
+            )
+            .unwrap();
+            let token_ids: Vec<String> = from_json(res).unwrap();
+            assert_eq!(expected, token_ids);
+        }
+    }
+
+    #[test]
+    fn test_query_available_tokens_verify_burned() {
+        let mut deps = mock_dependencies();
+        mock_cw721_owners(&mut deps, &[]);
+        mint_available(deps.as_mut().storage, &["token1", "token2"]);
+        // "token2" was burned, so it has no owner anymore.
+        deps.querier.update_wasm(|query| match query {
+            WasmQuery::Smart { msg, .. } => match from_json(msg).unwrap() {
+                Cw721QueryMsg::OwnerOf { token_id, .. } if token_id == "token1" => {
+                    SystemResult::Ok(ContractResult::Ok(
+                        to_json_binary(&OwnerOfResponse {
+                            owner: MOCK_CONTRACT_ADDR.to_string(),
+                            approvals: vec![],
+                        })
+                        .unwrap(),
+                    ))
+                }
+                Cw721QueryMsg::OwnerOf { .. } => {
+                    SystemResult::Ok(ContractResult::Err("token not found".to_string()))
+                }
+                _ => panic!("unexpected cw721 query"),
+            },
+            _ => panic!("unexpected query"),
+        });
+
+        let res = query(
+            deps.as_ref(),
+            mock_env(),
+            QueryMsg::AvailableTokens {
+                start_after: None,
+                limit: None,
+                verify: Some(true),
+                order: None,
+            },
+        )
+        .unwrap();
+        let token_ids: Vec<String> = from_json(res).unwrap();
+        assert_eq!(vec!["token1"], token_ids);
+    }
+
+    #[test]
+    fn test_start_sale_min_tokens_sold_exceeds_inventory() {
+        let mut deps = mock_dependencies();
+        init_ado(deps.as_mut());
+        mock_cw721_owners(&mut deps, &[]);
+        mint_available(deps.as_mut().storage, &["token1", "token2"]);
+
+        let err = start_sale(deps.as_mut(), 3).unwrap_err();
+        assert_eq!(
+            ContractError::CustomError {
+                msg: "min_tokens_sold (3) exceeds the 2 tokens available".to_string(),
+            },
+            err
+        );
+        assert!(STATE.may_load(deps.as_ref().storage).unwrap().is_none());
+
+        start_sale(deps.as_mut(), 2).unwrap();
+        let state = STATE.load(deps.as_ref().storage).unwrap();
+        assert_eq!(Uint128::new(2), state.min_tokens_sold);
+        assert_eq!(Uint128::new(2), state.total_tokens);
+    }
+
+    #[test]
+    fn test_reserve_token() {
+        let mut deps = mock_dependencies();
+        init_ado(deps.as_mut());
+        mock_cw721_owners(&mut deps, &[]);
+        mint_available(deps.as_mut().storage, &["token1", "token2"]);
+        let mut env = mock_env();
+        STATE
+            .save(
+                deps.as_mut().storage,
+                &State {
+                    end_time: Expiration::AtTime(env.block.time.plus_seconds(100)),
+                    ..mock_state()
+                },
+            )
+            .unwrap();
+
+        for token_id in ["token1", "token2"] {
+            execute_reserve_token(
+                ExecuteContext::new(deps.as_mut(), mock_info("A", &[]), env.clone()),
+                token_id.to_string(),
+                10_000,
+            )
+            .unwrap();
+        }
+
+        // Another wallet can't buy a reserved token, neither by id nor in bulk.
+        let info = mock_info("B", &coins(100, "uusd"));
+        let err = execute_purchase_by_token_id(
+            ExecuteContext::new(deps.as_mut(), info.clone(), env.clone()),
+            "token1".to_string(),
+            None,
+        )
+        .unwrap_err();
+        assert_eq!(ContractError::TokenNotAvailable {}, err);
+        let err = execute_purchase(
+            ExecuteContext::new(deps.as_mut(), info.clone(), env.clone()),
+            None,
+            None,
+        )
+        .unwrap_err();
+        assert_eq!(ContractError::AllTokensPurchased {}, err);
+
+        // The reserver can.
+        execute_purchase_by_token_id(
+            ExecuteContext::new(
+                deps.as_mut(),
+                mock_info("A", &coins(100, "uusd")),
+                env.clone(),
+            ),
+            "token1".to_string(),
+            None,
+        )
+        .unwrap();
+        assert!(!RESERVATIONS.has(deps.as_ref().storage, "token1"));
+
+        // Once the reservation lapses anyone can.
+        env.block.time = env.block.time.plus_seconds(11);
+        execute_purchase_by_token_id(
+            ExecuteContext::new(deps.as_mut(), info, env),
+            "token2".to_string(),
+            None,
+        )
+        .unwrap();
+        assert_eq!(
+            "B",
+            PURCHASES.load(deps.as_ref().storage, "B").unwrap()[0].purchaser
+        );
+    }
+
+    #[test]
+    fn test_reserve_token_limits() {
+        let mut deps = mock_dependencies();
+        mock_cw721_owners(&mut deps, &[]);
+        mint_available(deps.as_mut().storage, &["token1", "token2", "token3"]);
+        let mut env = mock_env();
+        STATE
+            .save(
+                deps.as_mut().storage,
+                &State {
+                    end_time: Expiration::AtTime(env.block.time.plus_seconds(100)),
+                    max_amount_per_wallet: 2,
+                    ..mock_state()
+                },
+            )
+            .unwrap();
+        let reserve = |deps: DepsMut, env: Env, token_id: &str, ttl_ms: u64| {
+            execute_reserve_token(
+                ExecuteContext::new(deps, mock_info("A", &[]), env),
+                token_id.to_string(),
+                ttl_ms,
+            )
+        };
+
+        assert_eq!(
+            ContractError::CustomError {
+                msg: "A token can't be reserved for more than 3600000 milliseconds".to_string()
+            },
+            reserve(deps.as_mut(), env.clone(), "token1", 3_600_001).unwrap_err()
+        );
+
+        // A wallet can't reserve more tokens than it can purchase, but can renew a reservation.
+        reserve(deps.as_mut(), env.clone(), "token1", 10_000).unwrap();
+        reserve(deps.as_mut(), env.clone(), "token2", 10_000).unwrap();
+        reserve(deps.as_mut(), env.clone(), "token2", 20_000).unwrap();
+        assert_eq!(
+            ContractError::PurchaseLimitReached {},
+            reserve(deps.as_mut(), env.clone(), "token3", 10_000).unwrap_err()
+        );
+
+        // Lapsed reservations no longer count.
+        env.block.time = env.block.time.plus_seconds(11);
+        reserve(deps.as_mut(), env.clone(), "token3", 10_000).unwrap();
+        assert!(!RESERVATIONS.has(deps.as_ref().storage, "token1"));
+
+        // A TTL too long to express in nanoseconds is rejected rather than overflowing.
+        CONFIG
+            .update(deps.as_mut().storage, |config| -> StdResult<_> {
+                Ok(Config {
+                    max_reservation_ttl_ms: Some(u64::MAX),
+                    ..config
+                })
+            })
+            .unwrap();
+        let err = reserve(deps.as_mut(), env, "token3", u64::MAX).unwrap_err();
+        assert!(matches!(err, ContractError::Std(StdError::Overflow { .. })));
+    }
+
+    #[test]
+    fn test_release_reservation() {
+        let mut deps = mock_dependencies();
+        mock_cw721_owners(&mut deps, &[]);
+        mint_available(deps.as_mut().storage, &["token1"]);
+        STATE.save(deps.as_mut().storage, &mock_state()).unwrap();
+        execute_reserve_token(
+            ExecuteContext::new(deps.as_mut(), mock_info("A", &[]), mock_env()),
+            "token1".to_string(),
+            10_000,
+        )
+        .unwrap();
+
+        let err = execute_release_reservation(
+            ExecuteContext::new(deps.as_mut(), mock_info("B", &[]), mock_env()),
+            "token1".to_string(),
+        )
+        .unwrap_err();
+        assert_eq!(ContractError::Unauthorized {}, err);
+
+        execute_release_reservation(
+            ExecuteContext::new(deps.as_mut(), mock_info("A", &[]), mock_env()),
+            "token1".to_string(),
+        )
+        .unwrap();
+        assert!(!RESERVATIONS.has(deps.as_ref().storage, "token1"));
+    }
+
+    #[test]
+    fn test_query_state_no_sale() {
+        let mut deps = mock_dependencies();
+        let res = query(deps.as_ref(), mock_env(), QueryMsg::State {}).unwrap();
+        assert_eq!(None, from_json::<Option<State>>(res).unwrap());
+
+        STATE.save(deps.as_mut().storage, &mock_state()).unwrap();
+        let res = query(deps.as_ref(), mock_env(), QueryMsg::State {}).unwrap();
+        assert_eq!(Some(mock_state()), from_json(res).unwrap());
+    }
+
+    #[test]
+    fn test_get_token_payment() {
+        // A 10% tax on a price of 100.
+        let price = Uint128::new(100);
+        let tax = Uint128::new(10);
+
+        // Tax exclusive: the buyer pays 110 and the recipient gets the full price.
+        let (charged, to_send) = get_token_payment(price, tax, price, false).unwrap();
+        assert_eq!(Uint128::new(10), charged);
+        assert_eq!(Uint128::new(100), to_send);
+        assert_eq!(Uint128::new(110), price + charged);
+
+        // Tax inclusive: the buyer pays 100, of which floor(10 * 100 / 110) = 9 is tax.
+        let (charged, to_send) = get_token_payment(price, tax, price, true).unwrap();
+        assert_eq!(Uint128::zero(), charged);
+        assert_eq!(Uint128::new(91), to_send);
+        assert_eq!(Uint128::new(100), price + charged);
+
+        // Without taxes both modes are the same.
+        assert_eq!(
+            (Uint128::zero(), price),
+            get_token_payment(price, Uint128::zero(), price, true).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_tax_inclusive_sale_state() {
+        let mut deps = mock_dependencies();
+        init_ado(deps.as_mut());
+        mock_cw721_owners(&mut deps, &[]);
+        mint_available(deps.as_mut().storage, &["token1"]);
+
+        let env = mock_env();
+        let res = execute_start_sale(
+            ExecuteContext::new(deps.as_mut(), mock_info("owner", &[]), env.clone()),
+            None,
+            MillisecondsExpiration::from_seconds(env.block.time.seconds() + 100),
+            coin(100, "uusd"),
+            Uint128::new(1),
+            None,
+            Recipient::from_string("recipient"),
+            SaleOptions {
+                tax_inclusive: Some(true),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert!(res.attributes.contains(&attr("tax_inclusive", "true")));
+        assert!(STATE.load(deps.as_ref().storage).unwrap().tax_inclusive);
+    }
+
+    #[test]
+    fn test_tax_inclusive_rates_paid_on_transfer() {
+        let mut deps = mock_dependencies();
+        init_ado(deps.as_mut());
+        mint_available(deps.as_mut().storage, &["token1", "token2"]);
+        STATE
+            .save(
+                deps.as_mut().storage,
+                &State {
+                    tax_inclusive: true,
+                    ..mock_state()
+                },
+            )
+            .unwrap();
+        mock_rates(&mut deps);
+
+        execute_purchase(
+            ExecuteContext::new(
+                deps.as_mut(),
+                mock_info("A", &coins(200, "uusd")),
+                mock_env(),
+            ),
+            Some(2),
+            None,
+        )
+        .unwrap();
+        // floor(10 * 100 / 110) = 9 of each price is tax, split between the taxes.
+        assert_eq!(
+            Uint128::new(182),
+            STATE.load(deps.as_ref().storage).unwrap().amount_to_send
+        );
+
+        let res = execute_end_sale(
+            ExecuteContext::new(deps.as_mut(), mock_info("anyone", &[]), mock_env()),
+            None,
+        )
+        .unwrap();
+        let payments: Vec<CosmosMsg> = res
+            .messages
+            .into_iter()
+            .map(|msg| msg.msg)
+            .filter(|msg| matches!(msg, CosmosMsg::Bank(_)))
+            .collect();
+        assert_eq!(
+            vec![
+                CosmosMsg::Bank(BankMsg::Send {
+                    to_address: "tax1".to_string(),
+                    amount: coins(8, "uusd"),
+                }),
+                CosmosMsg::Bank(BankMsg::Send {
+                    to_address: "tax2".to_string(),
+                    amount: coins(10, "uusd"),
+                }),
+            ],
+            payments
+        );
+    }
+
+    #[test]
+    fn test_audit_log() {
+        let mut deps = mock_dependencies();
+        init_ado(deps.as_mut());
+        mint_available(deps.as_mut().storage, &["token1"]);
+        mock_cw721_owners(&mut deps, &[]);
+
+        start_sale(deps.as_mut(), 1).unwrap();
+        STATE.remove(deps.as_mut().storage);
+        NUMBER_OF_TOKENS_AVAILABLE
+            .save(deps.as_mut().storage, &Uint128::zero())
+            .unwrap();
+        execute_update_token_contract(
+            ExecuteContext::new(deps.as_mut(), mock_info("owner", &[]), mock_env()),
+            AndrAddr::from_string("token_contract"),
+        )
+        .unwrap();
+
+        let res = query(
+            deps.as_ref(),
+            mock_env(),
+            QueryMsg::AuditLog {
+                start_after: None,
+                limit: None,
+            },
+        )
+        .unwrap();
+        let entries: Vec<AuditEntry> = from_json(res).unwrap();
+        let actions: Vec<(u64, &str, &str)> = entries
+            .iter()
+            .map(|entry| (entry.id, entry.actor.as_str(), entry.action.as_str()))
+            .collect();
+        assert_eq!(
+            vec![
+                (0, "owner", "start_sale"),
+                (1, "owner", "update_token_contract")
+            ],
+            actions
+        );
+        assert!(entries
+            .iter()
+            .all(|entry| entry.timestamp == mock_env().block.time));
+
+        let res = query(
+            deps.as_ref(),
+            mock_env(),
+            QueryMsg::AuditLog {
+                start_after: Some(0),
+                limit: None,
+            },
+        )
+        .unwrap();
+        let entries: Vec<AuditEntry> = from_json(res).unwrap();
+        assert_eq!(1, entries.len());
+        assert_eq!("update_token_contract", entries[0].action);
+    }
+
+    #[test]
+    fn test_claim_refund_retry_after_failure() {
+        let mut deps = mock_dependencies();
+        let mut env = mock_env();
+        STATE
+            .save(
+                deps.as_mut().storage,
+                &State {
+                    end_time: Expiration::AtTime(env.block.time.plus_seconds(100)),
+                    min_tokens_sold: Uint128::new(2),
+                    amount_sold: Uint128::one(),
+                    amount_received: Uint128::new(100),
+                    ..mock_state()
+                },
+            )
+            .unwrap();
+        let purchases = vec![mock_purchase("token1", "A", 0)];
+        PURCHASES
+            .save(deps.as_mut().storage, "A", &purchases)
+            .unwrap();
+        env.block.time = env.block.time.plus_seconds(101);
+
+        let res = execute_claim_refund(ExecuteContext::new(
+            deps.as_mut(),
+            mock_info("A", &[]),
+            env.clone(),
+        ))
+        .unwrap();
+        assert_eq!(
+            vec![SubMsg::reply_on_error(
+                CosmosMsg::Bank(BankMsg::Send {
+                    to_address: "A".to_string(),
+                    amount: coins(100, "uusd"),
+                }),
+                REFUND_REPLY_ID,
+            )],
+            res.messages
+        );
+
+        // The refund fails, so the purchases are restored and the refund can be claimed again.
+        let res = reply(
+            deps.as_mut(),
+            env.clone(),
+            Reply {
+                id: REFUND_REPLY_ID,
+                result: SubMsgResult::Err("refund failed".to_string()),
+            },
+        )
+        .unwrap();
+        assert!(res.attributes.contains(&attr("action", "refund_failed")));
+        assert_eq!(
+            purchases,
+            PURCHASES.load(deps.as_ref().storage, "A").unwrap()
+        );
+        assert!(PENDING_REFUNDS.is_empty(deps.as_ref().storage));
+
+        let res = execute_claim_refund(ExecuteContext::new(
+            deps.as_mut(),
+            mock_info("A", &[]),
+            env.clone(),
+        ))
+        .unwrap();
+        assert_eq!(1, res.messages.len());
+
+        // This time the refund succeeds, so it cannot be claimed a second time.
+        let err =
+            execute_claim_refund(ExecuteContext::new(deps.as_mut(), mock_info("A", &[]), env))
+                .unwrap_err();
+        assert_eq!(ContractError::NoPurchases {}, err);
+    }
+
+    #[test]
+    fn test_query_sale_denom() {
+        let mut deps = mock_dependencies();
+        let res = query(deps.as_ref(), mock_env(), QueryMsg::SaleDenom {}).unwrap();
+        assert_eq!(None, from_json::<Option<String>>(res).unwrap());
+
+        STATE.save(deps.as_mut().storage, &mock_state()).unwrap();
+        let res = query(deps.as_ref(), mock_env(), QueryMsg::SaleDenom {}).unwrap();
+        assert_eq!(Some("uusd".to_string()), from_json(res).unwrap());
+    }
+
+    #[test]
+    fn test_purchase_for_recipient() {
+        let mut deps = mock_dependencies();
+        init_ado(deps.as_mut());
+        mock_cw721_owners(&mut deps, &[]);
+        mint_available(deps.as_mut().storage, &["token1", "token2", "token3"]);
+        STATE
+            .save(
+                deps.as_mut().storage,
+                &State {
+                    max_amount_per_wallet: 2,
+                    total_tokens: Uint128::new(3),
+                    ..mock_state()
+                },
+            )
+            .unwrap();
+
+        // A gifts a friend a token, paying for it.
+        let res = execute_purchase_by_token_id(
+            ExecuteContext::new(
+                deps.as_mut(),
+                mock_info("A", &coins(100, "uusd")),
+                mock_env(),
+            ),
+            "token1".to_string(),
+            Some("friend".to_string()),
+        )
+        .unwrap();
+        assert!(res.attributes.contains(&attr("recipient", "friend")));
+        assert!(!PURCHASES.has(deps.as_ref().storage, "A"));
+        assert_eq!(
+            vec!["friend"],
+            PURCHASES
+                .load(deps.as_ref().storage, "friend")
+                .unwrap()
+                .iter()
+                .map(|p| p.purchaser.as_str())
+                .collect::<Vec<_>>()
+        );
+
+        // The gift counts towards the friend's wallet limit.
+        execute_purchase(
+            ExecuteContext::new(
+                deps.as_mut(),
+                mock_info("friend", &coins(100, "uusd")),
+                mock_env(),
+            ),
+            None,
+            None,
+        )
+        .unwrap();
+        let err = execute_purchase(
+            ExecuteContext::new(
+                deps.as_mut(),
+                mock_info("A", &coins(100, "uusd")),
+                mock_env(),
+            ),
+            Some(1),
+            Some("friend".to_string()),
+        )
+        .unwrap_err();
+        assert_eq!(ContractError::PurchaseLimitReached {}, err);
+
+        // At the end of the sale the gifted token is delivered to the friend.
+        let res = transfer_tokens_and_send_funds(
+            &mut deps.as_mut(),
+            mock_info("anyone", &[]),
+            mock_env(),
+            None,
+        )
+        .unwrap();
+        assert!(res.messages.iter().any(|msg| msg.msg
+            == CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: "token_contract".to_string(),
+                msg: encode_binary(&Cw721ExecuteMsg::TransferNft {
+                    recipient: AndrAddr::from_string("friend"),
+                    token_id: "token1".to_string(),
+                })
+                .unwrap(),
+                funds: vec![],
+            })));
+    }
+
+    #[test]
+    fn test_mint_requires_metadata() {
+        let mut deps = mock_dependencies();
+        NUMBER_OF_TOKENS_AVAILABLE
+            .save(deps.as_mut().storage, &Uint128::zero())
+            .unwrap();
+
+        // Fully on-chain metadata does not need a URI.
+        mint(
+            deps.as_mut().storage,
+            MOCK_CONTRACT_ADDR,
+            "token_contract".to_string(),
+            CrowdfundMintMsg {
+                token_id: "token1".to_string(),
+                owner: None,
+                token_uri: None,
+                extension: TokenExtension {
+                    publisher: "owner".to_string(),
+                },
+            },
+        )
+        .unwrap();
+        assert!(AVAILABLE_TOKENS.has(deps.as_ref().storage, "token1"));
+
+        let err = mint(
+            deps.as_mut().storage,
+            MOCK_CONTRACT_ADDR,
+            "token_contract".to_string(),
+            CrowdfundMintMsg {
+                token_id: "token2".to_string(),
+                owner: None,
+                token_uri: None,
+                extension: TokenExtension {
+                    publisher: "".to_string(),
+                },
+            },
+        )
+        .unwrap_err();
+        assert_eq!(
+            ContractError::CustomError {
+                msg: "Token token2 has neither a token_uri nor an extension".to_string()
+            },
+            err
+        );
+        assert!(!AVAILABLE_TOKENS.has(deps.as_ref().storage, "token2"));
+
+        // A URI has to be a URL.
+        let err = mint(
+            deps.as_mut().storage,
+            MOCK_CONTRACT_ADDR,
+            "token_contract".to_string(),
+            CrowdfundMintMsg {
+                token_id: "token3".to_string(),
+                owner: None,
+                token_uri: Some("token3.json".to_string()),
+                extension: TokenExtension {
+                    publisher: "".to_string(),
+                },
+            },
+        )
+        .unwrap_err();
+        assert_eq!(
+            ContractError::CustomError {
+                msg: "token_uri token3.json is not a valid URL".to_string()
+            },
+            err
+        );
+    }
+
+    #[test]
+    fn test_query_projected_outcome() {
+        let mut deps = mock_dependencies();
+        let env = mock_env();
+        mint_available(deps.as_mut().storage, &["token1"]);
+        let query_outcome = |deps: Deps, env: Env| -> Option<ProjectedOutcome> {
+            from_json(query(deps, env, QueryMsg::ProjectedOutcome {}).unwrap()).unwrap()
+        };
+        assert_eq!(None, query_outcome(deps.as_ref(), env.clone()));
+
+        let state = State {
+            end_time: Expiration::AtTime(env.block.time.plus_seconds(100)),
+            min_tokens_sold: Uint128::new(2),
+            amount_sold: Uint128::one(),
+            ..mock_state()
+        };
+        STATE.save(deps.as_mut().storage, &state).unwrap();
+        assert_eq!(
+            Some(ProjectedOutcome::Undetermined),
+            query_outcome(deps.as_ref(), env.clone())
+        );
+
+        // The minimum can't be met once the sale has ended.
+        let mut ended_env = env.clone();
+        ended_env.block.time = ended_env.block.time.plus_seconds(101);
+        assert_eq!(
+            Some(ProjectedOutcome::WillRefund),
+            query_outcome(deps.as_ref(), ended_env.clone())
+        );
+
+        // Nor when there aren't enough tokens left.
+        STATE
+            .save(
+                deps.as_mut().storage,
+                &State {
+                    min_tokens_sold: Uint128::new(3),
+                    ..state.clone()
+                },
+            )
+            .unwrap();
+        assert_eq!(
+            Some(ProjectedOutcome::WillRefund),
+            query_outcome(deps.as_ref(), env.clone())
+        );
+
+        STATE
+            .save(
+                deps.as_mut().storage,
+                &State {
+                    amount_sold: Uint128::new(2),
+                    ..state
+                },
+            )
+            .unwrap();
+        assert_eq!(
+            Some(ProjectedOutcome::WillSucceed),
+            query_outcome(deps.as_ref(), ended_env)
+        );
+    }
+
+    #[test]
+    fn test_migrate_legacy_sale() {
+        let mut deps = mock_dependencies();
+        init_ado(deps.as_mut());
+        mock_cw721_owners(&mut deps, &[]);
+        mint_available(deps.as_mut().storage, &["token2"]);
+        cw2::set_contract_version(deps.as_mut().storage, "crowdfund", "0.0.1").unwrap();
+
+        let env = mock_env();
+        let legacy_state = LegacyState {
+            end_time: Expiration::AtTime(env.block.time.plus_seconds(100)),
+            price: coin(100, "uusd"),
+            min_tokens_sold: Uint128::new(2),
+            max_amount_per_wallet: 5,
+            amount_sold: Uint128::one(),
+            amount_to_send: Uint128::new(100),
+            amount_transferred: Uint128::zero(),
+            recipient: Recipient::from_string("recipient"),
+        };
+        LEGACY_STATE
+            .save(deps.as_mut().storage, &legacy_state)
+            .unwrap();
+        LEGACY_PURCHASES
+            .save(
+                deps.as_mut().storage,
+                "A",
+                &vec![LegacyPurchase {
+                    token_id: "token1".to_string(),
+                    tax_amount: Uint128::zero(),
+                    msgs: vec![],
+                    purchaser: "A".to_string(),
+                }],
+            )
+            .unwrap();
+
+        migrate(deps.as_mut(), env.clone(), MigrateMsg {}).unwrap();
+
+        assert_eq!(
+            State {
+                start_time: Expiration::AtTime(env.block.time),
+                end_time: legacy_state.end_time,
+                min_tokens_sold: legacy_state.min_tokens_sold,
+                amount_sold: legacy_state.amount_sold,
+                amount_to_send: legacy_state.amount_to_send,
+                number_of_purchasers: 1,
+                amount_received: Uint128::new(100),
+                ..mock_state()
+            },
+            STATE.load(deps.as_ref().storage).unwrap()
+        );
+        assert_eq!(
+            vec![mock_purchase("token1", "A", 0)],
+            PURCHASES.load(deps.as_ref().storage, "A").unwrap()
+        );
+        assert_eq!(
+            Uint128::new(100),
+            REVENUE.load(deps.as_ref().storage, "uusd").unwrap()
+        );
+        assert_eq!(
+            CONTRACT_VERSION,
+            cw2::get_contract_version(deps.as_ref().storage)
+                .unwrap()
+                .version
+        );
+    }
+
+    #[test]
+    fn test_refund_all_in_batches() {
+        let mut deps = mock_dependencies();
+        let mut env = mock_env();
+        STATE
+            .save(
+                deps.as_mut().storage,
+                &State {
+                    end_time: Expiration::AtTime(env.block.time.plus_seconds(100)),
+                    min_tokens_sold: Uint128::new(5),
+                    amount_sold: Uint128::new(3),
+                    amount_received: Uint128::new(300),
+                    ..mock_state()
+                },
+            )
+            .unwrap();
+        for (i, purchaser) in ["A", "B", "C"].into_iter().enumerate() {
+            PURCHASES
+                .save(
+                    deps.as_mut().storage,
+                    purchaser,
+                    &vec![mock_purchase(&format!("token{i}"), purchaser, 0)],
+                )
+                .unwrap();
+        }
+
+        // Refunds can't be pushed while the sale is ongoing.
+        let err = execute_refund_all(
+            ExecuteContext::new(deps.as_mut(), mock_info("operator", &[]), env.clone()),
+            None,
+            Some(2),
+        )
+        .unwrap_err();
+        assert_eq!(ContractError::SaleNotEnded {}, err);
+
+        env.block.time = env.block.time.plus_seconds(101);
+        let refunded_to = |res: Response| -> Vec<String> {
+            res.messages
+                .into_iter()
+                .map(|msg| match msg.msg {
+                    CosmosMsg::Bank(BankMsg::Send { to_address, amount }) => {
+                        assert_eq!(coins(100, "uusd"), amount);
+                        to_address
+                    }
+                    _ => panic!("unexpected message"),
+                })
+                .collect()
+        };
+
+        let res = execute_refund_all(
+            ExecuteContext::new(deps.as_mut(), mock_info("operator", &[]), env.clone()),
+            None,
+            Some(2),
+        )
+        .unwrap();
+        assert_eq!(vec!["A", "B"], refunded_to(res));
+
+        let res = execute_refund_all(
+            ExecuteContext::new(deps.as_mut(), mock_info("operator", &[]), env.clone()),
+            None,
+            Some(2),
+        )
+        .unwrap();
+        assert_eq!(vec!["C"], refunded_to(res));
+        assert!(PURCHASES.is_empty(deps.as_ref().storage));
+    }
+
+    #[test]
+    fn test_refund_in_multiple_denoms() {
+        let mut deps = mock_dependencies();
+        STATE
+            .save(
+                deps.as_mut().storage,
+                &State {
+                    amount_received: Uint128::new(210),
+                    ..mock_state()
+                },
+            )
+            .unwrap();
+        let purchases = vec![
+            mock_purchase("token1", "A", 5),
+            Purchase {
+                price: coin(30, "uandr"),
+                ..mock_purchase("token2", "A", 3)
+            },
+            mock_purchase("token3", "A", 5),
+        ];
+        PURCHASES
+            .save(deps.as_mut().storage, "A", &purchases)
+            .unwrap();
+
+        let refund_msg = process_refund(deps.as_mut().storage, &purchases)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            CosmosMsg::Bank(BankMsg::Send {
+                to_address: "A".to_string(),
+                amount: vec![coin(33, "uandr"), coin(210, "uusd")],
+            }),
+            refund_msg.msg
+        );
+        assert!(!PURCHASES.has(deps.as_ref().storage, "A"));
+    }
+
+    #[test]
+    fn test_query_escrow_status() {
+        let mut deps = mock_dependencies();
+        let query_status = |deps: Deps| -> Option<EscrowStatusResponse> {
+            from_json(query(deps, mock_env(), QueryMsg::EscrowStatus {}).unwrap()).unwrap()
+        };
+        assert_eq!(None, query_status(deps.as_ref()));
+
+        // Two tokens were sold with 5 tax each, and the contract holds what it received.
+        deps.querier
+            .update_balance(MOCK_CONTRACT_ADDR, coins(210, "uusd"));
+        STATE
+            .save(
+                deps.as_mut().storage,
+                &State {
+                    amount_sold: Uint128::new(2),
+                    amount_to_send: Uint128::new(200),
+                    ..mock_state()
+                },
+            )
+            .unwrap();
+        PURCHASES
+            .save(
+                deps.as_mut().storage,
+                "A",
+                &vec![
+                    mock_purchase("token1", "A", 5),
+                    mock_purchase("token2", "A", 5),
+                ],
+            )
+            .unwrap();
+        assert_eq!(
+            Some(EscrowStatusResponse {
+                balance: coin(210, "uusd"),
+                amount_to_send: Uint128::new(200),
+                pending_refunds: Uint128::new(210),
+                shortfall: Uint128::zero(),
+            }),
+            query_status(deps.as_ref())
+        );
+
+        // A corrupted state that owes the recipient more than the contract holds.
+        STATE
+            .save(
+                deps.as_mut().storage,
+                &State {
+                    amount_sold: Uint128::new(2),
+                    amount_to_send: Uint128::new(500),
+                    ..mock_state()
+                },
+            )
+            .unwrap();
+        assert_eq!(
+            Uint128::new(290),
+            query_status(deps.as_ref()).unwrap().shortfall
+        );
+    }
+
+    #[test]
+    fn test_max_mints_per_block() {
+        let mut deps = mock_dependencies();
+        init_ado(deps.as_mut());
+        SALE_CONDUCTED.save(deps.as_mut().storage, &false).unwrap();
+        NUMBER_OF_TOKENS_AVAILABLE
+            .save(deps.as_mut().storage, &Uint128::zero())
+            .unwrap();
+        CONFIG
+            .save(
+                deps.as_mut().storage,
+                &Config {
+                    token_address: AndrAddr::from_string("token_contract"),
+                    can_mint_after_sale: true,
+                    max_mints_per_block: Some(2),
+                    max_reservation_ttl_ms: None,
+                },
+            )
+            .unwrap();
+        let mint_msgs = |token_ids: &[&str]| -> Vec<CrowdfundMintMsg> {
+            token_ids
+                .iter()
+                .map(|token_id| CrowdfundMintMsg {
+                    token_id: token_id.to_string(),
+                    owner: None,
+                    token_uri: Some("https://example.com/token.json".to_string()),
+                    extension: TokenExtension {
+                        publisher: "owner".to_string(),
+                    },
+                })
+                .collect()
+        };
+
+        let mut env = mock_env();
+        execute_mint(
+            ExecuteContext::new(deps.as_mut(), mock_info("owner", &[]), env.clone()),
+            mint_msgs(&["token1", "token2"]),
+        )
+        .unwrap();
+        let err = execute_mint(
+            ExecuteContext::new(deps.as_mut(), mock_info("owner", &[]), env.clone()),
+            mint_msgs(&["token3"]),
+        )
+        .unwrap_err();
+        assert_eq!(ContractError::MintLimitPerBlockReached { limit: 2 }, err);
+
+        env.block.height += 1;
+        execute_mint(
+            ExecuteContext::new(deps.as_mut(), mock_info("owner", &[]), env),
+            mint_msgs(&["token3"]),
+        )
+        .unwrap();
+        assert_eq!(
+            Uint128::new(3),
+            NUMBER_OF_TOKENS_AVAILABLE
+                .load(deps.as_ref().storage)
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn test_migrate_legacy_config() {
+        let mut deps = mock_dependencies();
+        init_ado(deps.as_mut());
+        cw2::set_contract_version(deps.as_mut().storage, "crowdfund", "0.0.1").unwrap();
+        LEGACY_CONFIG
+            .save(
+                deps.as_mut().storage,
+                &LegacyConfig {
+                    token_address: AndrAddr::from_string("token_contract"),
+                    can_mint_after_sale: false,
+                },
+            )
+            .unwrap();
+
+        migrate(deps.as_mut(), mock_env(), MigrateMsg {}).unwrap();
+
+        assert_eq!(
+            Config {
+                token_address: AndrAddr::from_string("token_contract"),
+                can_mint_after_sale: false,
+                max_mints_per_block: None,
+                max_reservation_ttl_ms: None,
+            },
+            CONFIG.load(deps.as_ref().storage).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_refund_to_refund_address() {
+        let mut deps = mock_dependencies();
+        STATE
+            .save(
+                deps.as_mut().storage,
+                &State {
+                    amount_received: Uint128::new(100),
+                    ..mock_state()
+                },
+            )
+            .unwrap();
+        let purchases = vec![mock_purchase("token1", "A", 0)];
+        PURCHASES
+            .save(deps.as_mut().storage, "A", &purchases)
+            .unwrap();
+
+        let err = execute_set_refund_address(
+            ExecuteContext::new(deps.as_mut(), mock_info("A", &[]), mock_env()),
+            "Invalid".to_string(),
+        )
+        .unwrap_err();
+        assert!(matches!(err, ContractError::Std(_)));
+
+        execute_set_refund_address(
+            ExecuteContext::new(deps.as_mut(), mock_info("A", &[]), mock_env()),
+            "refund_wallet".to_string(),
+        )
+        .unwrap();
+
+        let refund_msg = process_refund(deps.as_mut().storage, &purchases)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            CosmosMsg::Bank(BankMsg::Send {
+                to_address: "refund_wallet".to_string(),
+                amount: coins(100, "uusd"),
+            }),
+            refund_msg.msg
+        );
+    }
+
+    #[test]
+    fn test_lottery_refunds_to_refund_address() {
+        let mut deps = mock_dependencies();
+        init_ado(deps.as_mut());
+        mock_cw721_owners(&mut deps, &[]);
+        mint_available(deps.as_mut().storage, &[]);
+        let mut env = mock_env();
+        STATE
+            .save(
+                deps.as_mut().storage,
+                &State {
+                    end_time: Expiration::AtTime(env.block.time.plus_seconds(100)),
+                    sale_mode: SaleMode::Lottery,
+                    ..mock_state()
+                },
+            )
+            .unwrap();
+        for depositor in ["A", "B"] {
+            execute_deposit(
+                ExecuteContext::new(
+                    deps.as_mut(),
+                    mock_info(depositor, &coins(100, "uusd")),
+                    env.clone(),
+                ),
+                Some(1),
+            )
+            .unwrap();
+            execute_set_refund_address(
+                ExecuteContext::new(deps.as_mut(), mock_info(depositor, &[]), env.clone()),
+                format!("wallet_{}", depositor.to_lowercase()),
+            )
+            .unwrap();
+        }
+        let refund = |depositor: &str| {
+            vec![SubMsg::new(BankMsg::Send {
+                to_address: format!("wallet_{}", depositor.to_lowercase()),
+                amount: coins(100, "uusd"),
+            })]
+        };
+
+        env.block.time = env.block.time.plus_seconds(101);
+        let res = execute_withdraw_deposit(ExecuteContext::new(
+            deps.as_mut(),
+            mock_info("B", &[]),
+            env.clone(),
+        ))
+        .unwrap();
+        assert_eq!(refund("B"), res.messages);
+
+        // There are no tokens to draw, so every ticket is refunded.
+        let res = execute_draw(
+            ExecuteContext::new(deps.as_mut(), mock_info("owner", &[]), env),
+            42,
+            None,
+        )
+        .unwrap();
+        assert_eq!(refund("A"), res.messages);
+    }
+
+    #[test]
+    fn test_query_is_sold_out() {
+        let mut deps = mock_dependencies();
+        init_ado(deps.as_mut());
+        mock_cw721_owners(&mut deps, &[]);
+        mint_available(deps.as_mut().storage, &["token1", "token2"]);
+        let is_sold_out = |deps: Deps| -> bool {
+            from_json(query(deps, mock_env(), QueryMsg::IsSoldOut {}).unwrap()).unwrap()
+        };
+        assert!(!is_sold_out(deps.as_ref()));
+
+        STATE.save(deps.as_mut().storage, &mock_state()).unwrap();
+        execute_purchase(
+            ExecuteContext::new(
+                deps.as_mut(),
+                mock_info("A", &coins(100, "uusd")),
+                mock_env(),
+            ),
+            Some(1),
+            None,
+        )
+        .unwrap();
+        assert!(!is_sold_out(deps.as_ref()));
+
+        execute_purchase(
+            ExecuteContext::new(
+                deps.as_mut(),
+                mock_info("B", &coins(100, "uusd")),
+                mock_env(),
+            ),
+            Some(1),
+            None,
+        )
+        .unwrap();
+        assert!(is_sold_out(deps.as_ref()));
+    }
+
+    #[test]
+    fn test_update_token_contract_requires_confirmation() {
+        let mut deps = mock_dependencies();
+        init_ado(deps.as_mut());
+        mock_cw721_owners(&mut deps, &[]);
+        execute_set_secondary_approver(
+            ExecuteContext::new(deps.as_mut(), mock_info("owner", &[]), mock_env()),
+            Some(SecondaryApprover {
+                address: "approver".to_string(),
+                confirmation_window_seconds: 100,
+            }),
+        )
+        .unwrap();
+
+        let res = execute_update_token_contract(
+            ExecuteContext::new(deps.as_mut(), mock_info("owner", &[]), mock_env()),
+            AndrAddr::from_string("new_token_contract"),
+        )
+        .unwrap();
+        assert!(res.attributes.contains(&attr("proposal_id", "0")));
+        assert_eq!(
+            AndrAddr::from_string("token_contract"),
+            CONFIG.load(deps.as_ref().storage).unwrap().token_address
+        );
+
+        // Only the approver can confirm.
+        let err = execute_confirm_proposal(
+            ExecuteContext::new(deps.as_mut(), mock_info("owner", &[]), mock_env()),
+            0,
+        )
+        .unwrap_err();
+        assert_eq!(ContractError::Unauthorized {}, err);
+
+        execute_confirm_proposal(
+            ExecuteContext::new(deps.as_mut(), mock_info("approver", &[]), mock_env()),
+            0,
+        )
+        .unwrap();
+        assert_eq!(
+            AndrAddr::from_string("new_token_contract"),
+            CONFIG.load(deps.as_ref().storage).unwrap().token_address
+        );
+        assert!(PROPOSALS.is_empty(deps.as_ref().storage));
+    }
+
+    #[test]
+    fn test_proposal_expires() {
+        let mut deps = mock_dependencies();
+        init_ado(deps.as_mut());
+        mock_cw721_owners(&mut deps, &[]);
+        execute_set_secondary_approver(
+            ExecuteContext::new(deps.as_mut(), mock_info("owner", &[]), mock_env()),
+            Some(SecondaryApprover {
+                address: "approver".to_string(),
+                confirmation_window_seconds: 100,
+            }),
+        )
+        .unwrap();
+        execute_update_token_contract(
+            ExecuteContext::new(deps.as_mut(), mock_info("owner", &[]), mock_env()),
+            AndrAddr::from_string("new_token_contract"),
+        )
+        .unwrap();
+
+        let mut env = mock_env();
+        env.block.time = env.block.time.plus_seconds(100);
+        let err = execute_confirm_proposal(
+            ExecuteContext::new(deps.as_mut(), mock_info("approver", &[]), env),
+            0,
+        )
+        .unwrap_err();
+        assert_eq!(
+            ContractError::CustomError {
+                msg: "Proposal 0 has expired".to_string()
+            },
+            err
+        );
+        assert_eq!(
+            AndrAddr::from_string("token_contract"),
+            CONFIG.load(deps.as_ref().storage).unwrap().token_address
+        );
+    }
+
+    #[test]
+    fn test_sweep_expired_reservations() {
+        let mut deps = mock_dependencies();
+        let env = mock_env();
+        for (token_id, ttl) in [("token1", 10), ("token2", 100), ("token3", 10)] {
+            save_reservation(
+                deps.as_mut().storage,
+                token_id,
+                &Reservation {
+                    reserver: "A".to_string(),
+                    expiration: Expiration::AtTime(env.block.time.plus_seconds(ttl)),
+                },
+            )
+            .unwrap();
+        }
+
+        let mut later = env.clone();
+        later.block.time = later.block.time.plus_seconds(50);
+        let res = execute_sweep_expired_reservations(
+            ExecuteContext::new(deps.as_mut(), mock_info("anyone", &[]), later),
+            None,
+        )
+        .unwrap();
+        assert!(res.attributes.contains(&attr("number_of_swept", "2")));
+        assert_eq!(
+            vec!["token2".to_string()],
+            RESERVATIONS
+                .keys(deps.as_ref().storage, None, None, Order::Ascending)
+                .collect::<StdResult<Vec<_>>>()
+                .unwrap()
+        );
+        assert_eq!(
+            1,
+            RESERVER_TOKENS
+                .prefix("A")
+                .keys(deps.as_ref().storage, None, None, Order::Ascending)
+                .count()
+        );
+    }
+
+    #[test]
+    fn test_min_purchase() {
+        let mut deps = mock_dependencies();
+        init_ado(deps.as_mut());
+        mock_cw721_owners(&mut deps, &[]);
+        mint_available(deps.as_mut().storage, &["token1", "token2", "token3"]);
+        STATE
+            .save(
+                deps.as_mut().storage,
+                &State {
+                    min_purchase: Some(2),
+                    ..mock_state()
+                },
+            )
+            .unwrap();
+
+        let err = execute_purchase(
+            ExecuteContext::new(
+                deps.as_mut(),
+                mock_info("A", &coins(100, "uusd")),
+                mock_env(),
+            ),
+            Some(1),
+            None,
+        )
+        .unwrap_err();
+        assert_eq!(
+            ContractError::CustomError {
+                msg: "Must purchase at least 2 tokens".to_string()
+            },
+            err
+        );
+
+        execute_purchase(
+            ExecuteContext::new(
+                deps.as_mut(),
+                mock_info("A", &coins(200, "uusd")),
+                mock_env(),
+            ),
+            Some(2),
+            None,
+        )
+        .unwrap();
+
+        // Only a single token remains, which can be purchased on its own.
+        execute_purchase_by_token_id(
+            ExecuteContext::new(
+                deps.as_mut(),
+                mock_info("B", &coins(100, "uusd")),
+                mock_env(),
+            ),
+            "token3".to_string(),
+            None,
+        )
+        .unwrap();
+        assert!(AVAILABLE_TOKENS.is_empty(deps.as_ref().storage));
+    }
+
+    #[test]
+    fn test_query_refund_breakdown() {
+        let mut deps = mock_dependencies();
+        PURCHASES
+            .save(
+                deps.as_mut().storage,
+                "A",
+                &vec![
+                    mock_purchase("token1", "A", 5),
+                    Purchase {
+                        price: coin(30, "uandr"),
+                        ..mock_purchase("token2", "A", 3)
+                    },
+                    Purchase {
+                        price: coin(30, "uandr"),
+                        ..mock_purchase("token3", "A", 0)
+                    },
+                ],
+            )
+            .unwrap();
+
+        let query_breakdown = |deps: Deps, purchaser: &str| -> Vec<Coin> {
+            from_json(
+                query(
+                    deps,
+                    mock_env(),
+                    QueryMsg::RefundBreakdown {
+                        purchaser: purchaser.to_string(),
+                    },
+                )
+                .unwrap(),
+            )
+            .unwrap()
+        };
+        assert_eq!(
+            vec![coin(63, "uandr"), coin(105, "uusd")],
+            query_breakdown(deps.as_ref(), "A")
+        );
+        assert!(query_breakdown(deps.as_ref(), "B").is_empty());
+    }
+
+    #[test]
+    fn test_start_sale_hook() {
+        let mut deps = mock_dependencies();
+        init_ado(deps.as_mut());
+        mock_cw721_owners(&mut deps, &[]);
+        mint_available(deps.as_mut().storage, &["token1", "token2"]);
+
+        let env = mock_env();
+        let res = execute_start_sale(
+            ExecuteContext::new(deps.as_mut(), mock_info("owner", &[]), env.clone()),
+            None,
+            MillisecondsExpiration::from_seconds(env.block.time.seconds() + 100),
+            coin(100, "uusd"),
+            Uint128::new(1),
+            Some(5),
+            Recipient::from_string("recipient"),
+            SaleOptions {
+                on_start_hook: Some(AndrAddr::from_string("marketplace")),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        let state = STATE.load(deps.as_ref().storage).unwrap();
+        assert_eq!(
+            vec![CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: "marketplace".to_string(),
+                msg: encode_binary(&CrowdfundHookMsg::SaleStarted {
+                    start_time: state.start_time,
+                    end_time: state.end_time,
+                    price: coin(100, "uusd"),
+                    min_tokens_sold: Uint128::new(1),
+                    max_amount_per_wallet: 5,
+                    total_tokens: Uint128::new(2),
+                })
+                .unwrap(),
+                funds: vec![],
+            })],
+            res.messages.into_iter().map(|m| m.msg).collect::<Vec<_>>()
+        );
+
+        // Without a hook nothing is sent.
+        STATE.remove(deps.as_mut().storage);
+        let res = start_sale(deps.as_mut(), 1).unwrap();
+        assert!(res.messages.is_empty());
+    }
+
+    #[test]
+    fn test_partial_payouts() {
+        let mut deps = mock_dependencies();
+        init_ado(deps.as_mut());
+        mock_cw721_owners(&mut deps, &[]);
+        mint_available(
+            deps.as_mut().storage,
+            &["token1", "token2", "token3", "token4", "token5"],
+        );
+        STATE
+            .save(
+                deps.as_mut().storage,
+                &State {
+                    min_tokens_sold: Uint128::new(2),
+                    payout_threshold: Some(Uint128::new(200)),
+                    ..mock_state()
+                },
+            )
+            .unwrap();
+        let purchase = |deps: DepsMut, purchaser: &str, number_of_tokens: u32| {
+            execute_purchase(
+                ExecuteContext::new(
+                    deps,
+                    mock_info(purchaser, &coins(100 * number_of_tokens as u128, "uusd")),
+                    mock_env(),
+                ),
+                Some(number_of_tokens),
+                None,
+            )
+            .unwrap()
+        };
+
+        // The threshold is reached, but the funds are kept for refunds until the minimum is sold.
+        let res = purchase(deps.as_mut(), "A", 1);
+        assert!(res.messages.is_empty());
+        let res = purchase(deps.as_mut(), "B", 1);
+        assert_eq!(
+            vec![CosmosMsg::Bank(BankMsg::Send {
+                to_address: "recipient".to_string(),
+                amount: coins(200, "uusd"),
+            })],
+            res.messages.into_iter().map(|m| m.msg).collect::<Vec<_>>()
+        );
+        let state = STATE.load(deps.as_ref().storage).unwrap();
+        assert!(state.amount_to_send.is_zero());
+        assert!(REVENUE.is_empty(deps.as_ref().storage));
+
+        // Below the threshold again, so the rest is left for the end of the sale.
+        let res = purchase(deps.as_mut(), "C", 1);
+        assert!(res.messages.is_empty());
+        let state = STATE.load(deps.as_ref().storage).unwrap();
+        assert_eq!(Uint128::new(100), state.amount_to_send);
+        assert_eq!(
+            vec![coin(100, "uusd")],
+            take_revenue(deps.as_mut().storage).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_query_price_display() {
+        let mut deps = mock_dependencies();
+        let res = query(deps.as_ref(), mock_env(), QueryMsg::PriceDisplay {}).unwrap();
+        assert_eq!(
+            None,
+            from_json::<Option<PriceDisplayResponse>>(res).unwrap()
+        );
 
-    // Burn `limit` number of tokens
-    let burn_msgs = get_burn_messages(deps, env.contract.address.to_string(), limit)?;
+        init_ado(deps.as_mut());
+        mock_cw721_owners(&mut deps, &[]);
+        mint_available(deps.as_mut().storage, &["token1"]);
+        start_sale(deps.as_mut(), 1).unwrap();
 
-    if burn_msgs.is_empty() && purchases.is_empty() {
-        // When all tokens have been burned and all purchases have been refunded, the sale is over.
-        clear_state(deps.storage)?;
+        let res = query(deps.as_ref(), mock_env(), QueryMsg::PriceDisplay {}).unwrap();
+        assert_eq!(
+            Some(PriceDisplayResponse {
+                price: coin(100, "uusd"),
+                denom: "uusd".to_string(),
+            }),
+            from_json(res).unwrap()
+        );
     }
 
-    Ok(Response::new()
-        .add_attribute("action", "issue_refunds_and_burn_tokens")
-        .add_messages(refund_msgs)
-        .add_messages(burn_msgs))
-}
+    #[test]
+    fn test_update_can_mint_after_sale() {
+        let mut deps = mock_dependencies();
+        init_ado(deps.as_mut());
+        SALE_CONDUCTED.save(deps.as_mut().storage, &true).unwrap();
+        NUMBER_OF_TOKENS_AVAILABLE
+            .save(deps.as_mut().storage, &Uint128::zero())
+            .unwrap();
+        CONFIG
+            .save(
+                deps.as_mut().storage,
+                &Config {
+                    token_address: AndrAddr::from_string("token_contract"),
+                    can_mint_after_sale: false,
+                    max_mints_per_block: None,
+                    max_reservation_ttl_ms: None,
+                },
+            )
+            .unwrap();
+        let mint_token = |deps: DepsMut, token_id: &str| {
+            execute_mint(
+                ExecuteContext::new(deps, mock_info("owner", &[]), mock_env()),
+                vec![CrowdfundMintMsg {
+                    token_id: token_id.to_string(),
+                    owner: None,
+                    token_uri: Some("https://example.com/token.json".to_string()),
+                    extension: TokenExtension {
+                        publisher: "owner".to_string(),
+                    },
+                }],
+            )
+        };
+        let update = |deps: DepsMut, sender: &str, value: bool| {
+            execute_update_can_mint_after_sale(
+                ExecuteContext::new(deps, mock_info(sender, &[]), mock_env()),
+                value,
+            )
+        };
+        assert_eq!(
+            ContractError::CannotMintAfterSaleConducted {},
+            mint_token(deps.as_mut(), "token1").unwrap_err()
+        );
+        assert_eq!(
+            ContractError::Unauthorized {},
+            update(deps.as_mut(), "anyone", true).unwrap_err()
+        );
 
-fn transfer_tokens_and_send_funds(
-    deps: &mut DepsMut,
-    info: MessageInfo,
-    env: Env,
-) -> Result<Response, ContractError> {
-    let mut state = STATE.load(deps.storage)?;
-    let mut resp = Response::new();
+        let res = update(deps.as_mut(), "owner", true).unwrap();
+        assert_eq!(
+            Some("true"),
+            res.attributes
+                .iter()
+                .find(|attr| attr.key == "can_mint_after_sale")
+                .map(|attr| attr.value.as_str())
+        );
+        mint_token(deps.as_mut(), "token1").unwrap();
 
-    // Send the funds if they haven't been sent yet and if all of the tokens have been transferred.
-    if state.amount_transferred == state.amount_sold {
-        if state.amount_to_send > Uint128::zero() {
-            let funds = vec![Coin {
-                denom: state.price.denom.clone(),
-                amount: state.amount_to_send,
-            }];
+        update(deps.as_mut(), "owner", false).unwrap();
+        assert_eq!(
+            ContractError::CannotMintAfterSaleConducted {},
+            mint_token(deps.as_mut(), "token2").unwrap_err()
+        );
 
-            // Send funds to the recipient
-            match state.recipient.msg {
-                None => {
-                    resp = resp.add_submessage(
-                        state.recipient.generate_direct_msg(&deps.as_ref(), funds)?,
-                    );
-                }
-                Some(_) => {
-                    let amp_message = state
-                        .recipient
-                        .generate_amp_msg(&deps.as_ref(), Some(funds))
-                        .unwrap();
-                    let pkt =
-                        AMPPkt::new(info.sender, env.contract.address.clone(), vec![amp_message]);
-                    let kernel_address = ADOContract::default().get_kernel_address(deps.storage)?;
-                    let sub_msg = pkt.to_sub_msg(
-                        kernel_address,
-                        Some(coins(
-                            state.amount_to_send.u128(),
-                            state.price.denom.clone(),
-                        )),
-                        1,
-                    )?;
-                    resp = resp.add_submessage(sub_msg);
-                }
-            }
+        // The flag is fixed while a sale is ongoing.
+        STATE.save(deps.as_mut().storage, &mock_state()).unwrap();
+        assert_eq!(
+            ContractError::SaleStarted {},
+            update(deps.as_mut(), "owner", true).unwrap_err()
+        );
+    }
 
-            state.amount_to_send = Uint128::zero();
-            STATE.save(deps.storage, &state)?;
-        }
+    #[test]
+    fn test_query_purchase_taxes() {
+        let mut deps = mock_dependencies();
+        let query_taxes = |deps: Deps| -> Vec<PurchaseTaxResponse> {
+            let msg = QueryMsg::PurchaseTaxes {
+                purchaser: "A".to_string(),
+            };
+            from_json(query(deps, mock_env(), msg).unwrap()).unwrap()
+        };
+        assert!(query_taxes(deps.as_ref()).is_empty());
 
-        // Once all purchased tokens have been transferred, begin burning `limit` number of tokens
-        // that were not purchased.
-        let burn_msgs = get_burn_messages(&mut deps, env.contract.address.to_string(), None)?;
+        // The rates were raised between the two purchases.
+        PURCHASES
+            .save(
+                deps.as_mut().storage,
+                "A",
+                &vec![
+                    mock_purchase("token1", "A", 5),
+                    mock_purchase("token2", "A", 10),
+                ],
+            )
+            .unwrap();
+        assert_eq!(
+            vec![
+                PurchaseTaxResponse {
+                    token_id: "token1".to_string(),
+                    tax_amount: Uint128::new(5),
+                },
+                PurchaseTaxResponse {
+                    token_id: "token2".to_string(),
+                    tax_amount: Uint128::new(10),
+                },
+            ],
+            query_taxes(deps.as_ref())
+        );
+    }
 
-        if burn_msgs.is_empty() {
-            // When burn messages are empty, we have finished the sale, which is represented by
-            // having no State.
-            clear_state(deps.storage)?;
-        } else {
-            resp = resp.add_messages(burn_msgs);
-        }
-    } else {
-        // Continue transferring tokens to purchasers
-        let limit = None; // Transfer all remaining tokens
-        let mut transfer_msgs: Vec<CosmosMsg> = vec![];
+    #[test]
+    fn test_end_sale_sold_out_below_minimum_refunds() {
+        let mut deps = mock_dependencies();
+        init_ado(deps.as_mut());
+        mock_cw721_owners(&mut deps, &[]);
+        NUMBER_OF_TOKENS_AVAILABLE
+            .save(deps.as_mut().storage, &Uint128::zero())
+            .unwrap();
+        // Every token was sold, but fewer than the minimum.
+        STATE
+            .save(
+                deps.as_mut().storage,
+                &State {
+                    min_tokens_sold: Uint128::new(3),
+                    amount_sold: Uint128::new(2),
+                    amount_to_send: Uint128::new(200),
+                    total_tokens: Uint128::new(2),
+                    amount_received: Uint128::new(210),
+                    ..mock_state()
+                },
+            )
+            .unwrap();
+        add_revenue(deps.as_mut().storage, &coin(200, "uusd")).unwrap();
+        PURCHASES
+            .save(
+                deps.as_mut().storage,
+                "A",
+                &vec![
+                    mock_purchase("token1", "A", 5),
+                    mock_purchase("token2", "A", 5),
+                ],
+            )
+            .unwrap();
 
-        let purchases: Vec<Purchase> = PURCHASES
-            .range(deps.storage, None, None, Order::Ascending)
-            .flatten()
-            .take(limit.unwrap_or(DEFAULT_LIMIT) as usize)
-            .map(|(_v, p)| p)
-            .collect();
+        let res = execute_end_sale(
+            ExecuteContext::new(deps.as_mut(), mock_info("anyone", &[]), mock_env()),
+            None,
+        )
+        .unwrap();
+        assert_eq!(
+            vec![CosmosMsg::Bank(BankMsg::Send {
+                to_address: "A".to_string(),
+                amount: coins(210, "uusd"),
+            })],
+            res.messages.into_iter().map(|m| m.msg).collect::<Vec<_>>()
+        );
+        assert!(PURCHASES.is_empty(deps.as_ref().storage));
+    }
 
-        for purchase in purchases.iter() {
-            transfer_msgs.push(CosmosMsg::Wasm(WasmMsg::Execute {
-                contract_addr: state.token_address.clone(),
-                msg: encode_binary(&Cw721ExecuteMsg::TransferNft {
-                    recipient: Addr::unchecked(purchase.purchaser.clone()),
-                    token_id: purchase.token_id.clone(),
-                })?,
-                funds: vec![],
-            }));
+    #[test]
+    fn test_end_sale_early_stops_purchases() {
+        let mut deps = mock_dependencies();
+        init_ado(deps.as_mut());
+        mock_cw721_owners(&mut deps, &[]);
+        mint_available(deps.as_mut().storage, &["token1", "token2"]);
+        STATE.save(deps.as_mut().storage, &mock_state()).unwrap();
+        let purchase = |deps: DepsMut, purchaser: &str| {
+            execute_purchase(
+                ExecuteContext::new(deps, mock_info(purchaser, &coins(100, "uusd")), mock_env()),
+                Some(1),
+                None,
+            )
+        };
+        purchase(deps.as_mut(), "A").unwrap();
 
-            // Update state
-            state.amount_transferred += Uint128::one();
-        }
+        // The minimum is sold, so anyone can end the sale before its end time.
+        execute_end_sale(
+            ExecuteContext::new(deps.as_mut(), mock_info("anyone", &[]), mock_env()),
+            None,
+        )
+        .unwrap();
+        assert_eq!(
+            Expiration::AtTime(mock_env().block.time),
+            STATE.load(deps.as_ref().storage).unwrap().end_time
+        );
+        assert_eq!(
+            ContractError::NoOngoingSale {},
+            purchase(deps.as_mut(), "B").unwrap_err()
+        );
+    }
 
-        STATE.save(deps.storage, &state)?;
+    #[test]
+    fn test_anti_snipe_extension() {
+        let mut deps = mock_dependencies();
+        init_ado(deps.as_mut());
+        mock_cw721_owners(&mut deps, &[]);
+        mint_available(
+            deps.as_mut().storage,
+            &["token1", "token2", "token3", "token4"],
+        );
+        let env = mock_env();
+        let end_time = env.block.time.plus_seconds(100);
+        STATE
+            .save(
+                deps.as_mut().storage,
+                &State {
+                    end_time: Expiration::AtTime(end_time),
+                    anti_snipe: Some(AntiSnipe {
+                        window_ms: 60_000,
+                        extension_ms: 30_000,
+                        max_extension_ms: 45_000,
+                        extended_ms: 0,
+                    }),
+                    ..mock_state()
+                },
+            )
+            .unwrap();
+        let purchase =
+            |deps: &mut OwnedDeps<MockStorage, MockApi, MockQuerier>, purchaser: &str, env: Env| {
+                execute_purchase(
+                    ExecuteContext::new(
+                        deps.as_mut(),
+                        mock_info(purchaser, &coins(100, "uusd")),
+                        env,
+                    ),
+                    Some(1),
+                    None,
+                )
+                .unwrap();
+                STATE.load(deps.as_ref().storage).unwrap().end_time
+            };
 
-        resp = resp.add_messages(transfer_msgs);
-    }
+        // Outside of the window the end time stays the same.
+        assert_eq!(
+            Expiration::AtTime(end_time),
+            purchase(&mut deps, "A", env.clone())
+        );
 
-    Ok(resp.add_attribute("action", "transfer_tokens_and_send_funds"))
-}
-/// Processes a vector of purchases for the SAME user by merging all funds into a single BankMsg.
-/// The given purchaser is then removed from `PURCHASES`.
-///
-/// ## Arguments
-/// * `storage`  - Mutable reference to Storage
-/// * `purchase` - Vector of purchases for the same user to issue a refund message for.
-/// * `price`    - The price of a token
-///
-/// Returns an `Option<CosmosMsg>` which is `None` when the amount to refund is zero.
-fn process_refund(
-    storage: &mut dyn Storage,
-    purchases: &[Purchase],
-    price: &Coin,
-) -> Option<CosmosMsg> {
-    let purchaser = purchases[0].purchaser.clone();
-    // Remove each entry as they get processed.
-    PURCHASES.remove(storage, &purchaser);
-    // Reduce a user's purchases into one message. While the tax paid on each item should
-    // be the same, it is not guaranteed given that the rates module is mutable during the
-    // sale.
-    let amount = purchases
-        .iter()
-        // This represents the total amount of funds they sent for each purchase.
-        .map(|p| p.tax_amount + price.amount)
-        // Adds up all of the purchases.
-        .reduce(|accum, item| accum + item)
-        .unwrap_or_else(Uint128::zero);
-
-    if amount > Uint128::zero() {
-        Some(CosmosMsg::Bank(BankMsg::Send {
-            to_address: purchaser,
-            amount: vec![Coin {
-                denom: price.denom.clone(),
-                amount,
-            }],
-        }))
-    } else {
-        None
+        let mut late_env = env;
+        late_env.block.time = end_time.minus_seconds(10);
+        assert_eq!(
+            Expiration::AtTime(end_time.plus_seconds(30)),
+            purchase(&mut deps, "B", late_env.clone())
+        );
+
+        // The sale is only extended by what is left of the maximum, and then no longer.
+        late_env.block.time = end_time.plus_seconds(20);
+        assert_eq!(
+            Expiration::AtTime(end_time.plus_seconds(45)),
+            purchase(&mut deps, "C", late_env.clone())
+        );
+        late_env.block.time = end_time.plus_seconds(40);
+        assert_eq!(
+            Expiration::AtTime(end_time.plus_seconds(45)),
+            purchase(&mut deps, "D", late_env)
+        );
     }
-}
 
-fn get_burn_messages(
-    deps: &mut DepsMut,
-    address: String,
-    limit: usize,
-) -> Result<Vec<CosmosMsg>, ContractError> {
-    let config = CONFIG.load(deps.storage)?;
-    let token_address = config.token_address.get_raw_address(&deps.as_ref())?;
-    let tokens_to_burn = query_tokens(&deps.querier, token_address.to_string(), address, limit)?;
+    #[test]
+    fn test_failed_amp_payout_is_recoverable() {
+        let mut deps = mock_dependencies();
+        init_ado(deps.as_mut());
+        let recipient = Recipient::new("recipient", Some(to_json_binary(&"msg").unwrap()));
+        STATE
+            .save(
+                deps.as_mut().storage,
+                &State {
+                    recipient: recipient.clone(),
+                    amount_to_send: Uint128::new(200),
+                    ..mock_state()
+                },
+            )
+            .unwrap();
+        add_revenue(deps.as_mut().storage, &coin(200, "uusd")).unwrap();
 
-    tokens_to_burn
-        .into_iter()
-        .map(|token_id| {
-            // Any token that is burnable has been added to this map, and so must be removed.
-            AVAILABLE_TOKENS.remove(deps.storage, &token_id);
-            Ok(CosmosMsg::Wasm(WasmMsg::Execute {
-                contract_addr: token_address.to_string(),
-                funds: vec![],
-                msg: encode_binary(&Cw721ExecuteMsg::Burn { token_id })?,
-            }))
-        })
-        .collect()
-}
+        // Pay out as the end of the sale does.
+        let revenue = take_revenue(deps.as_mut().storage).unwrap();
+        track_pending_payouts(deps.as_mut().storage, &revenue).unwrap();
+        let payout_msgs = get_payout_messages(
+            &deps.as_ref(),
+            &mock_info("anyone", &[]),
+            &mock_env(),
+            &recipient,
+            revenue,
+        )
+        .unwrap();
+        STATE
+            .update(deps.as_mut().storage, |mut state| -> StdResult<_> {
+                state.amount_to_send = Uint128::zero();
+                Ok(state)
+            })
+            .unwrap();
+        assert_eq!(1, payout_msgs.len());
+        assert_eq!(PAYOUT_REPLY_ID, payout_msgs[0].id);
+        assert_eq!(ReplyOn::Error, payout_msgs[0].reply_on);
+        match &payout_msgs[0].msg {
+            CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr,
+                funds,
+                ..
+            }) => {
+                assert_eq!("kernel", contract_addr);
+                assert_eq!(&coins(200, "uusd"), funds);
+            }
+            msg => panic!("unexpected message {msg:?}"),
+        }
 
-fn clear_state(storage: &mut dyn Storage) -> Result<(), ContractError> {
-    STATE.remove(storage);
-    NUMBER_OF_TOKENS_AVAILABLE.save(storage, &Uint128::zero())?;
+        // The kernel fails to deliver the payout, so the funds are marked as unsent again.
+        reply(
+            deps.as_mut(),
+            mock_env(),
+            Reply {
+                id: payout_msgs[0].id,
+                result: SubMsgResult::Err("amp delivery failed".to_string()),
+            },
+        )
+        .unwrap();
+        let state = STATE.load(deps.as_ref().storage).unwrap();
+        assert_eq!(Uint128::new(200), state.amount_to_send);
+        assert_eq!(
+            vec![coin(200, "uusd")],
+            take_revenue(deps.as_mut().storage).unwrap()
+        );
+    }
 
-    Ok(())
-}
+    #[test]
+    fn test_oracle_priced_purchase() {
+        let mut deps = mock_dependencies();
+        init_ado(deps.as_mut());
+        mint_available(deps.as_mut().storage, &["token1", "token2", "token3"]);
+        STATE
+            .save(
+                deps.as_mut().storage,
+                &State {
+                    oracle: Some(OraclePrice {
+                        address: "oracle".to_string(),
+                        usd_price: Uint128::new(100),
+                    }),
+                    ..mock_state()
+                },
+            )
+            .unwrap();
+        // One uusd is worth half a USD.
+        deps.querier.update_wasm(|query| match query {
+            WasmQuery::Smart { contract_addr, msg } if contract_addr == "oracle" => {
+                let OracleQueryMsg::Rate { denom } = from_json(msg).unwrap();
+                assert_eq!("uusd", denom);
+                SystemResult::Ok(ContractResult::Ok(
+                    to_json_binary(&OracleRateResponse {
+                        rate: Decimal::percent(50),
+                    })
+                    .unwrap(),
+                ))
+            }
+            WasmQuery::Raw { .. } => SystemResult::Ok(ContractResult::Ok(
+                to_json_binary(&Addr::unchecked("vfs")).unwrap(),
+            )),
+            _ => panic!("unexpected query"),
+        });
 
-fn query_tokens(
-    querier: &QuerierWrapper,
-    token_address: String,
-    owner: String,
-    limit: usize,
-) -> Result<Vec<String>, ContractError> {
-    let res: TokensResponse = querier.query(&QueryRequest::Wasm(WasmQuery::Smart {
-        contract_addr: token_address,
-        msg: encode_binary(&Cw721QueryMsg::Tokens {
-            owner,
-            start_after: None,
-            limit: Some(limit as u32),
-        })?,
-    }))?;
-    Ok(res.tokens)
-}
+        let err = execute_purchase(
+            ExecuteContext::new(
+                deps.as_mut(),
+                mock_info("A", &coins(300, "uusd")),
+                mock_env(),
+            ),
+            Some(2),
+            None,
+        )
+        .unwrap_err();
+        assert_eq!(ContractError::InsufficientFunds {}, err);
 
-#[cfg_attr(not(feature = "library"), entry_point)]
-pub fn query(deps: Deps, env: Env, msg: QueryMsg) -> Result<Binary, ContractError> {
-    match msg {
-        QueryMsg::State {} => encode_binary(&query_state(deps)?),
-        QueryMsg::Config {} => encode_binary(&query_config(deps)?),
-        QueryMsg::AvailableTokens { start_after, limit } => {
-            encode_binary(&query_available_tokens(deps, start_after, limit)?)
-        }
-        QueryMsg::IsTokenAvailable { id } => encode_binary(&query_is_token_available(deps, id)),
-        _ => ADOContract::default().query(deps, env, msg),
+        execute_purchase(
+            ExecuteContext::new(
+                deps.as_mut(),
+                mock_info("A", &coins(400, "uusd")),
+                mock_env(),
+            ),
+            Some(2),
+            None,
+        )
+        .unwrap();
+        let purchases = PURCHASES.load(deps.as_ref().storage, "A").unwrap();
+        assert!(purchases
+            .iter()
+            .all(|purchase| purchase.price == coin(200, "uusd")));
+        let state = STATE.load(deps.as_ref().storage).unwrap();
+        assert_eq!(coin(200, "uusd"), state.price);
+        assert_eq!(Uint128::new(400), state.amount_to_send);
     }
-}
 
-fn query_state(deps: Deps) -> Result<State, ContractError> {
-    Ok(STATE.load(deps.storage)?)
-}
+    #[test]
+    fn test_abort_sale() {
+        let mut deps = mock_dependencies();
+        init_ado(deps.as_mut());
+        mock_cw721_owners(&mut deps, &[]);
+        NUMBER_OF_TOKENS_AVAILABLE
+            .save(deps.as_mut().storage, &Uint128::zero())
+            .unwrap();
+        // The minimum was sold, which would otherwise pay out.
+        STATE
+            .save(
+                deps.as_mut().storage,
+                &State {
+                    amount_sold: Uint128::new(3),
+                    total_tokens: Uint128::new(3),
+                    amount_received: Uint128::new(300),
+                    ..mock_state()
+                },
+            )
+            .unwrap();
+        for (token_id, purchaser) in [("token1", "A"), ("token2", "B"), ("token3", "C")] {
+            PURCHASES
+                .save(
+                    deps.as_mut().storage,
+                    purchaser,
+                    &vec![mock_purchase(token_id, purchaser, 0)],
+                )
+                .unwrap();
+        }
+        let abort = |deps: DepsMut, sender: &str| {
+            execute_abort_sale(
+                ExecuteContext::new(deps, mock_info(sender, &[]), mock_env()),
+                Some(2),
+            )
+        };
+        assert_eq!(
+            ContractError::Unauthorized {},
+            abort(deps.as_mut(), "anyone").unwrap_err()
+        );
 
-fn query_config(deps: Deps) -> Result<Config, ContractError> {
-    Ok(CONFIG.load(deps.storage)?)
-}
+        let res = abort(deps.as_mut(), "owner").unwrap();
+        assert_eq!(2, res.messages.len());
+        let state = STATE.load(deps.as_ref().storage).unwrap();
+        assert!(state.aborted);
+        assert!(state.end_time.is_expired(&mock_env().block));
 
-fn query_available_tokens(
-    deps: Deps,
-    start_after: Option<String>,
-    limit: Option<u32>,
-) -> Result<Vec<String>, ContractError> {
-    get_available_tokens(deps.storage, start_after, limit)
-}
+        // The last purchaser is refunded, after which the sale is over.
+        let res = abort(deps.as_mut(), "owner").unwrap();
+        assert_eq!(
+            vec![CosmosMsg::Bank(BankMsg::Send {
+                to_address: "C".to_string(),
+                amount: coins(100, "uusd"),
+            })],
+            res.messages.into_iter().map(|m| m.msg).collect::<Vec<_>>()
+        );
+        assert!(STATE.may_load(deps.as_ref().storage).unwrap().is_some());
+        abort(deps.as_mut(), "owner").unwrap();
+        assert!(STATE.may_load(deps.as_ref().storage).unwrap().is_none());
+    }
 
-fn query_is_token_available(deps: Deps, id: String) -> bool {
-    AVAILABLE_TOKENS.has(deps.storage, &id)
-}
+    #[test]
+    fn test_query_can_mint() {
+        let mut deps = mock_dependencies();
+        mock_cw721_owners(&mut deps, &[]);
+        CONFIG
+            .update(deps.as_mut().storage, |mut config| -> StdResult<_> {
+                config.can_mint_after_sale = false;
+                Ok(config)
+            })
+            .unwrap();
+        SALE_CONDUCTED.save(deps.as_mut().storage, &false).unwrap();
+        let can_mint = |deps: Deps| -> bool {
+            from_json(query(deps, mock_env(), QueryMsg::CanMint {}).unwrap()).unwrap()
+        };
+        assert!(can_mint(deps.as_ref()));
 
-#[cfg_attr(not(feature = "library"), entry_point)]
-pub fn migrate(deps: DepsMut, _env: Env, _msg: MigrateMsg) -> Result<Response, ContractError> {
-    ADOContract::default().migrate(deps, CONTRACT_NAME, CONTRACT_VERSION)
-}
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use cosmwasm_std::testing::{mock_dependencies, mock_env};
-    use cosmwasm_std::{coin, Response};
+        STATE.save(deps.as_mut().storage, &mock_state()).unwrap();
+        SALE_CONDUCTED.save(deps.as_mut().storage, &true).unwrap();
+        assert!(!can_mint(deps.as_ref()));
+
+        STATE.remove(deps.as_mut().storage);
+        assert!(!can_mint(deps.as_ref()));
+        CONFIG
+            .update(deps.as_mut().storage, |mut config| -> StdResult<_> {
+                config.can_mint_after_sale = true;
+                Ok(config)
+            })
+            .unwrap();
+        assert!(can_mint(deps.as_ref()));
+    }
 
-    // Test cases for the end_condition_met function
     #[test]
-    fn test_end_condition_met_expired() {
-        let mut state = State {
-            start_time: 0,
-            end_time: 10,
-            min_tokens_sold: 100,
-            amount_sold: 50,
-            total_tokens: 200,
-            target_percentage_sold: None,
-            max_duration: None,
-            owner_ended: false,
+    fn test_prune_purchases() {
+        let mut deps = mock_dependencies();
+        let prune = |deps: DepsMut| {
+            execute_prune_purchases(
+                ExecuteContext::new(deps, mock_info("anyone", &[]), mock_env()),
+                None,
+            )
         };
-        let env = mock_env(11, "anyone");
-        assert_eq!(end_condition_met(&state, &env), true);
+        // The ongoing sale still needs its purchases.
+        STATE.save(deps.as_mut().storage, &mock_state()).unwrap();
+        assert_eq!(
+            ContractError::SaleNotEnded {},
+            prune(deps.as_mut()).unwrap_err()
+        );
+
+        // The sale failed, and only A is still owed a refund.
+        STATE
+            .save(
+                deps.as_mut().storage,
+                &State {
+                    end_time: Expiration::AtHeight(0),
+                    min_tokens_sold: Uint128::new(5),
+                    ..mock_state()
+                },
+            )
+            .unwrap();
+        PURCHASES
+            .save(
+                deps.as_mut().storage,
+                "A",
+                &vec![mock_purchase("token1", "A", 5)],
+            )
+            .unwrap();
+        PURCHASES
+            .save(
+                deps.as_mut().storage,
+                "B",
+                &vec![Purchase {
+                    price: coin(0, "uusd"),
+                    ..mock_purchase("token2", "B", 0)
+                }],
+            )
+            .unwrap();
+        PURCHASES.save(deps.as_mut().storage, "C", &vec![]).unwrap();
+
+        let res = prune(deps.as_mut()).unwrap();
+        assert!(res.attributes.contains(&attr("number_of_pruned", "2")));
+        let remaining: Vec<String> = PURCHASES
+            .keys(deps.as_ref().storage, None, None, Order::Ascending)
+            .collect::<StdResult<_>>()
+            .unwrap();
+        assert_eq!(vec!["A".to_string()], remaining);
+
+        // Once the sale is over, nothing is owed anymore.
+        STATE.remove(deps.as_mut().storage);
+        prune(deps.as_mut()).unwrap();
+        assert!(PURCHASES.is_empty(deps.as_ref().storage));
     }
 
     #[test]
-    fn test_end_condition_met_minimum_sold() {
-        let mut state = State {
-            start_time: 0,
-            end_time: 100,
-            min_tokens_sold: 100,
-            amount_sold: 150,
-            total_tokens: 200,
-            target_percentage_sold: None,
-            max_duration: None,
-            owner_ended: false,
+    fn test_query_payout_destination() {
+        let mut deps = mock_dependencies();
+        let query_destination = |deps: Deps| -> Option<PayoutDestinationResponse> {
+            from_json(query(deps, mock_env(), QueryMsg::PayoutDestination {}).unwrap()).unwrap()
         };
-        let env = mock_env(50, "anyone");
-        assert_eq!(end_condition_met(&state, &env), true);
+        assert_eq!(None, query_destination(deps.as_ref()));
+
+        STATE.save(deps.as_mut().storage, &mock_state()).unwrap();
+        assert_eq!(
+            Some(PayoutDestinationResponse {
+                address: Addr::unchecked("recipient"),
+                is_amp: false,
+            }),
+            query_destination(deps.as_ref())
+        );
+
+        STATE
+            .save(
+                deps.as_mut().storage,
+                &State {
+                    recipient: Recipient::new("recipient", Some(to_json_binary(&"msg").unwrap())),
+                    ..mock_state()
+                },
+            )
+            .unwrap();
+        assert_eq!(
+            Some(PayoutDestinationResponse {
+                address: Addr::unchecked("recipient"),
+                is_amp: true,
+            }),
+            query_destination(deps.as_ref())
+        );
     }
 
     #[test]
-    fn test_end_condition_met_target_percentage() {
-        let mut state = State {
-            start_time: 0,
-            end_time: 100,
-            min_tokens_sold: 0,
-            amount_sold: 110,
-            total_tokens: 200,
-            target_percentage_sold: Some(50),
-            max_duration: None,
-            owner_ended: false,
+    fn test_min_purchasers() {
+        let mut deps = mock_dependencies();
+        init_ado(deps.as_mut());
+        mock_cw721_owners(&mut deps, &[]);
+        mint_available(deps.as_mut().storage, &["token1", "token2"]);
+        STATE
+            .save(
+                deps.as_mut().storage,
+                &State {
+                    min_tokens_sold: Uint128::new(2),
+                    min_purchasers: Some(2),
+                    ..mock_state()
+                },
+            )
+            .unwrap();
+
+        // A single whale buys every token.
+        execute_purchase(
+            ExecuteContext::new(
+                deps.as_mut(),
+                mock_info("A", &coins(200, "uusd")),
+                mock_env(),
+            ),
+            Some(2),
+            None,
+        )
+        .unwrap();
+        let state = STATE.load(deps.as_ref().storage).unwrap();
+        assert_eq!(Uint128::new(2), state.amount_sold);
+        assert_eq!(1, state.number_of_purchasers);
+        let res = query(deps.as_ref(), mock_env(), QueryMsg::ProjectedOutcome {}).unwrap();
+        assert_eq!(
+            Some(ProjectedOutcome::WillRefund),
+            from_json::<Option<ProjectedOutcome>>(res).unwrap()
+        );
+
+        let res = execute_end_sale(
+            ExecuteContext::new(deps.as_mut(), mock_info("anyone", &[]), mock_env()),
+            None,
+        )
+        .unwrap();
+        assert_eq!(
+            vec![CosmosMsg::Bank(BankMsg::Send {
+                to_address: "A".to_string(),
+                amount: coins(200, "uusd"),
+            })],
+            res.messages.into_iter().map(|m| m.msg).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_available_tokens_in_mint_order() {
+        let mut deps = mock_dependencies();
+        mint_available(deps.as_mut().storage, &["2", "10", "1"]);
+        let query_tokens = |deps: Deps, start_after: Option<&str>, order| -> Vec<String> {
+            let msg = QueryMsg::AvailableTokens {
+                start_after: start_after.map(str::to_string),
+                limit: Some(2),
+                verify: None,
+                order,
+            };
+            from_json(query(deps, mock_env(), msg).unwrap()).unwrap()
         };
-        let env = mock_env(50, "anyone");
-        assert_eq!(end_condition_met(&state, &env), true);
+
+        assert_eq!(vec!["1", "10"], query_tokens(deps.as_ref(), None, None));
+        assert_eq!(
+            vec!["2", "10"],
+            query_tokens(deps.as_ref(), None, Some(TokenOrder::Minted))
+        );
+        assert_eq!(
+            vec!["1"],
+            query_tokens(deps.as_ref(), Some("10"), Some(TokenOrder::Minted))
+        );
+
+        // Purchased tokens drop out of the order.
+        remove_available_token(deps.as_mut().storage, "10").unwrap();
+        assert_eq!(
+            vec!["2", "1"],
+            query_tokens(deps.as_ref(), None, Some(TokenOrder::Minted))
+        );
+
+        // Tokens made available before the order was tracked are ordered by token id on upgrade.
+        AVAILABLE_TOKENS
+            .save(deps.as_mut().storage, "0", &true)
+            .unwrap();
+        migrate_token_sequences(deps.as_mut().storage).unwrap();
+        assert_eq!(
+            vec!["1", "0"],
+            query_tokens(deps.as_ref(), Some("2"), Some(TokenOrder::Minted))
+        );
+        assert_eq!(
+            Some(4),
+            NEXT_TOKEN_SEQUENCE.may_load(deps.as_ref().storage).unwrap()
+        );
     }
 
     #[test]
-    fn test_end_condition_met_max_duration() {
-        let mut state = State {
-            start_time: 0,
-            end_time: 100,
-            min_tokens_sold: 0,
-            amount_sold: 0,
-            total_tokens: 200,
-            target_percentage_sold: None,
-            max_duration: Some(50),
-            owner_ended: false,
+    fn test_batch_purchase_by_token_id() {
+        let mut deps = mock_dependencies();
+        init_ado(deps.as_mut());
+        mock_cw721_owners(&mut deps, &[]);
+        mint_available(deps.as_mut().storage, &["token1", "token2"]);
+        STATE.save(deps.as_mut().storage, &mock_state()).unwrap();
+        let token_ids = vec![
+            "token1".to_string(),
+            "token2".to_string(),
+            "token3".to_string(),
+        ];
+        let batch_purchase = |deps: DepsMut, allow_partial: bool| {
+            execute_batch_purchase_by_token_id(
+                ExecuteContext::new(deps, mock_info("A", &coins(300, "uusd")), mock_env()),
+                token_ids.clone(),
+                allow_partial,
+                None,
+            )
         };
-        let env = mock_env(100, "anyone");
-        assert_eq!(end_condition_met(&state, &env), true);
+
+        // "token3" is not available, so nothing is bought.
+        assert_eq!(
+            ContractError::TokenNotAvailable {},
+            batch_purchase(deps.as_mut(), false).unwrap_err()
+        );
+        assert!(PURCHASES
+            .may_load(deps.as_ref().storage, "A")
+            .unwrap()
+            .is_none());
+
+        let res = batch_purchase(deps.as_mut(), true).unwrap();
+        assert_eq!(
+            vec![CosmosMsg::Bank(BankMsg::Send {
+                to_address: "A".to_string(),
+                amount: coins(100, "uusd"),
+            })],
+            res.messages.into_iter().map(|m| m.msg).collect::<Vec<_>>()
+        );
+        let purchases = PURCHASES.load(deps.as_ref().storage, "A").unwrap();
+        assert_eq!(
+            vec!["token1", "token2"],
+            purchases
+                .iter()
+                .map(|purchase| purchase.token_id.as_str())
+                .collect::<Vec<_>>()
+        );
+        assert!(AVAILABLE_TOKENS.is_empty(deps.as_ref().storage));
     }
 
     #[test]
-    fn test_end_condition_met_owner_ended() {
-        let mut state = State {
-            start_time: 0,
-            end_time: 100,
-            min_tokens_sold: 0,
-            amount_sold: 0,
-            total_tokens: 200,
-            target_percentage_sold: None,
-            max_duration: None,
-            owner_ended: true,
+    fn test_query_progress_bps() {
+        let mut deps = mock_dependencies();
+        let query_progress = |deps: Deps| -> Option<ProgressBpsResponse> {
+            from_json(query(deps, mock_env(), QueryMsg::ProgressBps {}).unwrap()).unwrap()
         };
-        let env = mock_env(50, "anyone");
-        assert_eq!(end_condition_met(&state, &env), true);
+        assert_eq!(None, query_progress(deps.as_ref()));
+
+        for (amount_sold, total_tokens, min_tokens_sold, sold_bps, min_bps) in [
+            (0, 3, 2, 0, 0),
+            (1, 3, 2, 3_333, 5_000),
+            (2, 3, 2, 6_666, 10_000),
+            (3, 3, 2, 10_000, 10_000),
+            (1, 7, 0, 1_428, 10_000),
+        ] {
+            STATE
+                .save(
+                    deps.as_mut().storage,
+                    &State {
+                        amount_sold: Uint128::new(amount_sold),
+                        total_tokens: Uint128::new(total_tokens),
+                        min_tokens_sold: Uint128::new(min_tokens_sold),
+                        ..mock_state()
+                    },
+                )
+                .unwrap();
+            assert_eq!(
+                Some(ProgressBpsResponse { sold_bps, min_bps }),
+                query_progress(deps.as_ref())
+            );
+        }
     }
 
     #[test]
-    fn test_end_condition_met_not_met() {
-        let mut state = State {
-            start_time: 0,
-            end_time: 100,
-            min_tokens_sold: 100,
-            amount_sold: 50,
-            total_tokens: 200,
-            target_percentage_sold: Some(50),
-            max_duration: Some(50),
-            owner_ended: false,
+    fn test_refund_overpayment() {
+        for refund_overpayment in [true, false] {
+            let mut deps = mock_dependencies();
+            init_ado(deps.as_mut());
+            mock_cw721_owners(&mut deps, &[]);
+            mint_available(deps.as_mut().storage, &["token1"]);
+            STATE
+                .save(
+                    deps.as_mut().storage,
+                    &State {
+                        refund_overpayment,
+                        ..mock_state()
+                    },
+                )
+                .unwrap();
+
+            let res = execute_purchase_by_token_id(
+                ExecuteContext::new(
+                    deps.as_mut(),
+                    mock_info("A", &coins(150, "uusd")),
+                    mock_env(),
+                ),
+                "token1".to_string(),
+                None,
+            )
+            .unwrap();
+            let msgs: Vec<CosmosMsg> = res.messages.into_iter().map(|m| m.msg).collect();
+            let state = STATE.load(deps.as_ref().storage).unwrap();
+            let purchases = PURCHASES.load(deps.as_ref().storage, "A").unwrap();
+            if refund_overpayment {
+                assert_eq!(
+                    vec![CosmosMsg::Bank(BankMsg::Send {
+                        to_address: "A".to_string(),
+                        amount: coins(50, "uusd"),
+                    })],
+                    msgs
+                );
+                assert_eq!(Uint128::new(100), state.amount_to_send);
+            } else {
+                // The overpayment is paid out with the sale, or refunded if it fails.
+                assert!(msgs.is_empty());
+                assert_eq!(Uint128::new(150), state.amount_to_send);
+                assert_eq!(Uint128::new(50), purchases[0].tip_amount);
+                assert_eq!(
+                    vec![coin(150, "uusd")],
+                    get_refund_amount(&purchases).unwrap()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_decline_purchase() {
+        let mut deps = mock_dependencies();
+        let mut env = mock_env();
+        NUMBER_OF_TOKENS_AVAILABLE
+            .save(deps.as_mut().storage, &Uint128::zero())
+            .unwrap();
+        STATE
+            .save(
+                deps.as_mut().storage,
+                &State {
+                    end_time: Expiration::AtTime(env.block.time.plus_seconds(100)),
+                    amount_sold: Uint128::new(2),
+                    amount_to_send: Uint128::new(200),
+                    number_of_purchasers: 2,
+                    amount_received: Uint128::new(205),
+                    ..mock_state()
+                },
+            )
+            .unwrap();
+        add_revenue(deps.as_mut().storage, &coin(200, "uusd")).unwrap();
+        PURCHASES
+            .save(
+                deps.as_mut().storage,
+                "A",
+                &vec![mock_purchase("token1", "A", 5)],
+            )
+            .unwrap();
+        PURCHASES
+            .save(
+                deps.as_mut().storage,
+                "B",
+                &vec![mock_purchase("token2", "B", 0)],
+            )
+            .unwrap();
+        env.block.time = env.block.time.plus_seconds(101);
+
+        let decline = |deps: DepsMut, sender: &str, token_id: &str| {
+            execute_decline_purchase(
+                ExecuteContext::new(deps, mock_info(sender, &[]), env.clone()),
+                token_id.to_string(),
+            )
         };
-        let env = mock_env(50, "anyone");
-        assert_eq!(end_condition_met(&state, &env), false);
+
+        let res = decline(deps.as_mut(), "A", "token1").unwrap();
+        assert_eq!(
+            vec![SubMsg::reply_on_error(
+                CosmosMsg::Bank(BankMsg::Send {
+                    to_address: "A".to_string(),
+                    amount: coins(105, "uusd"),
+                }),
+                REFUND_REPLY_ID,
+            )],
+            res.messages
+        );
+        let state = STATE.load(deps.as_ref().storage).unwrap();
+        assert_eq!(Uint128::one(), state.amount_sold);
+        assert_eq!(Uint128::new(100), state.amount_to_send);
+        assert_eq!(Uint128::new(105), state.amount_refunded);
+        assert_eq!(1, state.number_of_purchasers);
+        assert!(!PURCHASES.has(deps.as_ref().storage, "A"));
+        assert!(AVAILABLE_TOKENS.has(deps.as_ref().storage, "token1"));
+        assert_eq!(
+            vec![coin(100, "uusd")],
+            take_revenue(deps.as_mut().storage).unwrap()
+        );
+
+        // Once the tokens are being transferred, purchases can't be declined anymore.
+        STATE
+            .update(deps.as_mut().storage, |mut state| -> StdResult<_> {
+                state.min_tokens_sold = Uint128::zero();
+                state.amount_transferred = Uint128::one();
+                Ok(state)
+            })
+            .unwrap();
+        let err = decline(deps.as_mut(), "B", "token2").unwrap_err();
+        assert_eq!(
+            ContractError::CustomError {
+                msg: "Tokens have already been transferred".to_string(),
+            },
+            err
+        );
     }
 
-    // Test cases for the execute_end_sale function
     #[test]
-    fn test_execute_end_sale_expired() {
-        let mut deps = mock_dependencies(&[]);
-        let env = mock_env(11, "anyone");
-        let result = execute_end_sale(ExecuteContext { deps, env, ..Default::default() }, None);
-        assert_eq!(result.unwrap_err().msg, ContractError::SaleNotEnded {}.to_string());
+    fn test_query_token_status() {
+        let mut deps = mock_dependencies();
+        mint_available(deps.as_mut().storage, &["token1"]);
+        TOKEN_PURCHASERS
+            .save(deps.as_mut().storage, "token2", &"A".to_string())
+            .unwrap();
+
+        assert_eq!(
+            TokenStatus::Available,
+            query_token_status(deps.as_ref(), "token1".to_string()).unwrap()
+        );
+        assert_eq!(
+            TokenStatus::Sold {
+                purchaser: "A".to_string()
+            },
+            query_token_status(deps.as_ref(), "token2".to_string()).unwrap()
+        );
+        assert_eq!(
+            TokenStatus::Unknown,
+            query_token_status(deps.as_ref(), "token3".to_string()).unwrap()
+        );
     }
 
     #[test]
-    fn test_execute_end_sale_minimum_sold_not_met() {
-        let mut deps = mock_dependencies(&[]);
-        let env = mock_env(50, "anyone");
-        let result = execute_end_sale(ExecuteContext { deps, env, ..Default::default() }, None);
-        assert_eq!(result.unwrap_err().msg, ContractError::MinSalesExceeded {}.to_string());
+    fn test_start_sale_empty_denom() {
+        let mut deps = mock_dependencies();
+        init_ado(deps.as_mut());
+        NUMBER_OF_TOKENS_AVAILABLE
+            .save(deps.as_mut().storage, &Uint128::new(1))
+            .unwrap();
+        let env = mock_env();
+        let err = execute_start_sale(
+            ExecuteContext::new(deps.as_mut(), mock_info("owner", &[]), env.clone()),
+            None,
+            MillisecondsExpiration::from_seconds(env.block.time.seconds() + 100),
+            coin(100, ""),
+            Uint128::one(),
+            None,
+            Recipient::from_string("recipient"),
+            SaleOptions::default(),
+        )
+        .unwrap_err();
+        assert_eq!(
+            ContractError::CustomError {
+                msg: "price denom must not be empty".to_string(),
+            },
+            err
+        );
     }
 
     #[test]
-    fn test_execute_end_sale_minimum_sold_met() {
-        let mut deps = mock_dependencies(&[]);
-        let env = mock_env(150, "anyone");
-        let result = execute_end_sale(ExecuteContext { deps, env, ..Default::default() }, None);
-        assert!(result.is_ok());
-        assert_eq!(result.unwrap().attributes[0].clone().value, "end_sale");
+    fn test_batch_purchase_enforce_distinct_tokens() {
+        let mut deps = mock_dependencies();
+        mint_available(deps.as_mut().storage, &["token1", "token2"]);
+        STATE
+            .save(
+                deps.as_mut().storage,
+                &State {
+                    enforce_distinct_tokens: true,
+                    ..mock_state()
+                },
+            )
+            .unwrap();
+
+        let err = execute_batch_purchase_by_token_id(
+            ExecuteContext::new(
+                deps.as_mut(),
+                mock_info("A", &coins(300, "uusd")),
+                mock_env(),
+            ),
+            vec![
+                "token1".to_string(),
+                "token2".to_string(),
+                "token1".to_string(),
+            ],
+            true,
+            None,
+        )
+        .unwrap_err();
+        assert_eq!(
+            ContractError::CustomError {
+                msg: "Token id token1 is listed more than once".to_string(),
+            },
+            err
+        );
+        assert!(!PURCHASES.has(deps.as_ref().storage, "A"));
     }
 
     #[test]
-    fn test_execute_end_sale_target_percentage_met() {
-        let mut deps = mock_dependencies(&[]);
-        let env = mock_env(150, "anyone");
-        let mut state = State {
-            start_time: 0,
-            end_time: 100,
-            min_tokens_sold: 0,
-            amount_sold: 150,
-            total_tokens: 200,
-            target_percentage_sold: Some(75),
-            max_duration: None,
-            owner_ended: false,
+    fn test_end_sale_transfers_in_batches() {
+        let mut deps = mock_dependencies();
+        init_ado(deps.as_mut());
+        mock_cw721_owners(&mut deps, &[]);
+        NUMBER_OF_TOKENS_AVAILABLE
+            .save(deps.as_mut().storage, &Uint128::zero())
+            .unwrap();
+        STATE
+            .save(
+                deps.as_mut().storage,
+                &State {
+                    amount_sold: Uint128::new(3),
+                    total_tokens: Uint128::new(3),
+                    ..mock_state()
+                },
+            )
+            .unwrap();
+        PURCHASES
+            .save(
+                deps.as_mut().storage,
+                "A",
+                &vec![
+                    mock_purchase("token1", "A", 0),
+                    mock_purchase("token2", "A", 0),
+                ],
+            )
+            .unwrap();
+        PURCHASES
+            .save(
+                deps.as_mut().storage,
+                "B",
+                &vec![mock_purchase("token3", "B", 0)],
+            )
+            .unwrap();
+        TOKEN_PURCHASERS
+            .save(deps.as_mut().storage, "token1", &"A".to_string())
+            .unwrap();
+        let end_sale = |deps: DepsMut, limit: u32| -> Vec<(String, String)> {
+            let res = execute_end_sale(
+                ExecuteContext::new(deps, mock_info("anyone", &[]), mock_env()),
+                Some(limit),
+            )
+            .unwrap();
+            res.messages
+                .into_iter()
+                .map(|msg| match msg.msg {
+                    CosmosMsg::Wasm(WasmMsg::Execute { msg, .. }) => {
+                        match from_json(msg).unwrap() {
+                            Cw721ExecuteMsg::TransferNft {
+                                recipient,
+                                token_id,
+                            } => (recipient.to_string(), token_id),
+                            _ => panic!("unexpected message"),
+                        }
+                    }
+                    _ => panic!("unexpected message"),
+                })
+                .collect()
         };
-        let result = execute_end_sale(ExecuteContext { deps, env, ..Default::default() }, None);
-        assert!(result.is_ok());
-        assert_eq!(result.unwrap().attributes[0].clone().value, "end_sale");
+
+        // Every token is transferred exactly once, even when a purchaser's tokens are split
+        // across batches.
+        assert_eq!(
+            vec![("A".to_string(), "token1".to_string())],
+            end_sale(deps.as_mut(), 1)
+        );
+        assert_eq!(
+            vec![mock_purchase("token2", "A", 0)],
+            PURCHASES.load(deps.as_ref().storage, "A").unwrap()
+        );
+        assert_eq!(
+            vec![
+                ("A".to_string(), "token2".to_string()),
+                ("B".to_string(), "token3".to_string())
+            ],
+            end_sale(deps.as_mut(), 2)
+        );
+        assert_eq!(
+            Uint128::new(3),
+            STATE
+                .load(deps.as_ref().storage)
+                .unwrap()
+                .amount_transferred
+        );
+        assert!(PURCHASES.is_empty(deps.as_ref().storage));
+
+        // Nothing is left for the next sale once the sale is over.
+        assert!(end_sale(deps.as_mut(), 2).is_empty());
+        assert!(STATE.may_load(deps.as_ref().storage).unwrap().is_none());
+        assert!(TOKEN_PURCHASERS.is_empty(deps.as_ref().storage));
     }
 
     #[test]
-    fn test_execute_end_sale_max_duration_met() {
-        let mut deps = mock_dependencies(&[]);
-        let env = mock_env(150, "anyone");
-        let mut state = State {
-            start_time: 0,
-            end_time: 100,
-            min_tokens_sold: 0,
-            amount_sold: 150,
-            total_tokens: 200,
-            target_percentage_sold: None,
-            max_duration: Some(50),
-            owner_ended: false
-        }}
+    fn test_change_secondary_approver_requires_confirmation() {
+        let mut deps = mock_dependencies();
+        init_ado(deps.as_mut());
+        mock_cw721_owners(&mut deps, &[]);
+
+        let err = execute_set_secondary_approver(
+            ExecuteContext::new(deps.as_mut(), mock_info("owner", &[]), mock_env()),
+            Some(SecondaryApprover {
+                address: "approver".to_string(),
+                confirmation_window_seconds: 0,
+            }),
+        )
+        .unwrap_err();
+        assert_eq!(
+            ContractError::CustomError {
+                msg: "The confirmation window must be greater than zero".to_string(),
+            },
+            err
+        );
+
+        execute_set_secondary_approver(
+            ExecuteContext::new(deps.as_mut(), mock_info("owner", &[]), mock_env()),
+            Some(SecondaryApprover {
+                address: "approver".to_string(),
+                confirmation_window_seconds: 100,
+            }),
+        )
+        .unwrap();
+        execute_update_token_contract(
+            ExecuteContext::new(deps.as_mut(), mock_info("owner", &[]), mock_env()),
+            AndrAddr::from_string("new_token_contract"),
+        )
+        .unwrap();
+
+        // Removing the approver is only proposed.
+        let res = execute_set_secondary_approver(
+            ExecuteContext::new(deps.as_mut(), mock_info("owner", &[]), mock_env()),
+            None,
+        )
+        .unwrap();
+        assert!(res.attributes.contains(&attr("proposal_id", "1")));
+        assert!(SECONDARY_APPROVER
+            .may_load(deps.as_ref().storage)
+            .unwrap()
+            .is_some());
+
+        execute_confirm_proposal(
+            ExecuteContext::new(deps.as_mut(), mock_info("approver", &[]), mock_env()),
+            1,
+        )
+        .unwrap();
+        assert!(SECONDARY_APPROVER
+            .may_load(deps.as_ref().storage)
+            .unwrap()
+            .is_none());
+        // The pending token contract update is dropped along with the approver.
+        assert!(PROPOSALS.is_empty(deps.as_ref().storage));
+
+        // Proposal ids are not reused.
+        execute_set_secondary_approver(
+            ExecuteContext::new(deps.as_mut(), mock_info("owner", &[]), mock_env()),
+            Some(SecondaryApprover {
+                address: "approver".to_string(),
+                confirmation_window_seconds: 100,
+            }),
+        )
+        .unwrap();
+        let res = execute_update_token_contract(
+            ExecuteContext::new(deps.as_mut(), mock_info("owner", &[]), mock_env()),
+            AndrAddr::from_string("new_token_contract"),
+        )
+        .unwrap();
+        assert!(res.attributes.contains(&attr("proposal_id", "2")));
+    }
+
+    #[test]
+    fn test_abort_sale_after_transfers_started() {
+        let mut deps = mock_dependencies();
+        init_ado(deps.as_mut());
+        mock_cw721_owners(&mut deps, &[]);
+        NUMBER_OF_TOKENS_AVAILABLE
+            .save(deps.as_mut().storage, &Uint128::zero())
+            .unwrap();
+        STATE
+            .save(
+                deps.as_mut().storage,
+                &State {
+                    amount_sold: Uint128::new(2),
+                    total_tokens: Uint128::new(2),
+                    amount_received: Uint128::new(200),
+                    ..mock_state()
+                },
+            )
+            .unwrap();
+        for (token_id, purchaser) in [("token1", "A"), ("token2", "B")] {
+            PURCHASES
+                .save(
+                    deps.as_mut().storage,
+                    purchaser,
+                    &vec![mock_purchase(token_id, purchaser, 0)],
+                )
+                .unwrap();
+        }
+        execute_end_sale(
+            ExecuteContext::new(deps.as_mut(), mock_info("anyone", &[]), mock_env()),
+            Some(1),
+        )
+        .unwrap();
+
+        let err = execute_abort_sale(
+            ExecuteContext::new(deps.as_mut(), mock_info("owner", &[]), mock_env()),
+            None,
+        )
+        .unwrap_err();
+        assert_eq!(
+            ContractError::CustomError {
+                msg: "Tokens have already been transferred".to_string(),
+            },
+            err
+        );
+        assert!(!STATE.load(deps.as_ref().storage).unwrap().aborted);
+    }
+
+    #[test]
+    fn test_decline_purchase_refund_failed() {
+        let mut deps = mock_dependencies();
+        let mut env = mock_env();
+        NUMBER_OF_TOKENS_AVAILABLE
+            .save(deps.as_mut().storage, &Uint128::zero())
+            .unwrap();
+        STATE
+            .save(
+                deps.as_mut().storage,
+                &State {
+                    end_time: Expiration::AtTime(env.block.time.plus_seconds(100)),
+                    amount_sold: Uint128::new(2),
+                    amount_to_send: Uint128::new(190),
+                    number_of_purchasers: 1,
+                    amount_received: Uint128::new(200),
+                    ..mock_state()
+                },
+            )
+            .unwrap();
+        add_revenue(deps.as_mut().storage, &coin(190, "uusd")).unwrap();
+        // Royalties took 20 of the first token's price, and 10 was overpaid as a tip.
+        PURCHASES
+            .save(
+                deps.as_mut().storage,
+                "A",
+                &vec![
+                    Purchase {
+                        amount_to_send: Uint128::new(80),
+                        tip_amount: Uint128::new(10),
+                        ..mock_purchase("token1", "A", 0)
+                    },
+                    mock_purchase("token2", "A", 0),
+                ],
+            )
+            .unwrap();
+        env.block.time = env.block.time.plus_seconds(101);
+
+        let res = execute_decline_purchase(
+            ExecuteContext::new(deps.as_mut(), mock_info("A", &[]), env.clone()),
+            "token1".to_string(),
+        )
+        .unwrap();
+        assert_eq!(
+            CosmosMsg::Bank(BankMsg::Send {
+                to_address: "A".to_string(),
+                amount: coins(110, "uusd"),
+            }),
+            res.messages[0].msg
+        );
+        let state = STATE.load(deps.as_ref().storage).unwrap();
+        assert_eq!(Uint128::new(100), state.amount_to_send);
+        assert_eq!(1, PURCHASES.load(deps.as_ref().storage, "A").unwrap().len());
+        assert!(!TOKEN_PURCHASERS.has(deps.as_ref().storage, "token1"));
+
+        // A failed refund undoes the decline.
+        restore_failed_refund(deps.as_mut(), REFUND_REPLY_ID, "error".to_string()).unwrap();
+        let state = STATE.load(deps.as_ref().storage).unwrap();
+        assert_eq!(Uint128::new(2), state.amount_sold);
+        assert_eq!(Uint128::new(190), state.amount_to_send);
+        assert_eq!(Uint128::zero(), state.amount_refunded);
+        assert_eq!(1, state.number_of_purchasers);
+        assert_eq!(2, PURCHASES.load(deps.as_ref().storage, "A").unwrap().len());
+        assert_eq!(
+            "A",
+            TOKEN_PURCHASERS
+                .load(deps.as_ref().storage, "token1")
+                .unwrap()
+        );
+        assert!(!AVAILABLE_TOKENS.has(deps.as_ref().storage, "token1"));
+        assert_eq!(
+            Uint128::zero(),
+            NUMBER_OF_TOKENS_AVAILABLE
+                .load(deps.as_ref().storage)
+                .unwrap()
+        );
+        assert_eq!(
+            vec![coin(190, "uusd")],
+            take_revenue(deps.as_mut().storage).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_decline_purchase_after_payout() {
+        let mut deps = mock_dependencies();
+        let mut env = mock_env();
+        STATE
+            .save(
+                deps.as_mut().storage,
+                &State {
+                    end_time: Expiration::AtTime(env.block.time.plus_seconds(100)),
+                    amount_sold: Uint128::new(2),
+                    number_of_purchasers: 1,
+                    ..mock_state()
+                },
+            )
+            .unwrap();
+        PENDING_PAYOUTS
+            .save(deps.as_mut().storage, PAYOUT_REPLY_ID, &coin(200, "uusd"))
+            .unwrap();
+        PURCHASES
+            .save(
+                deps.as_mut().storage,
+                "A",
+                &vec![
+                    mock_purchase("token1", "A", 0),
+                    mock_purchase("token2", "A", 0),
+                ],
+            )
+            .unwrap();
+        env.block.time = env.block.time.plus_seconds(101);
+
+        let err = execute_decline_purchase(
+            ExecuteContext::new(deps.as_mut(), mock_info("A", &[]), env),
+            "token1".to_string(),
+        )
+        .unwrap_err();
+        assert_eq!(
+            ContractError::CustomError {
+                msg: "Funds have already been paid out during the sale".to_string(),
+            },
+            err
+        );
     }
+}
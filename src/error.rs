@@ -1,13 +1,91 @@
-use cosmwasm_std::StdError;
+use andromeda_std::error::ContractError as AndrContractError;
+use cosmwasm_std::{OverflowError, StdError};
+use cw_utils::PaymentError;
 use thiserror::Error;
 
-#[derive(Error, Debug)]
+#[derive(Error, Debug, PartialEq)]
 pub enum ContractError {
     #[error("{0}")]
     Std(#[from] StdError),
 
+    #[error("{0}")]
+    Andromeda(#[from] AndrContractError),
+
+    #[error("{0}")]
+    Payment(#[from] PaymentError),
+
     #[error("Unauthorized")]
     Unauthorized {},
-    // Add any other custom errors you like here.
-    // Look at https://docs.rs/thiserror/1.0.21/thiserror/ for details.
+
+    #[error("{msg}")]
+    CustomError { msg: String },
+
+    #[error("UnsupportedOperation")]
+    UnsupportedOperation {},
+
+    #[error("InsufficientFunds")]
+    InsufficientFunds {},
+
+    #[error("Invalid zero amount")]
+    InvalidZeroAmount {},
+
+    #[error("Invalid denom")]
+    InvalidDenom {},
+
+    #[error("StartTimeAfterEndTime")]
+    StartTimeAfterEndTime {},
+
+    #[error("Limit must not be zero")]
+    LimitMustNotBeZero {},
+
+    #[error("No Ongoing Sale")]
+    NoOngoingSale {},
+
+    #[error("Sale has already started")]
+    SaleStarted {},
+
+    #[error("Sale not ended")]
+    SaleNotEnded {},
+
+    #[error("Min sales exceeded")]
+    MinSalesExceeded {},
+
+    #[error("No purchases")]
+    NoPurchases {},
+
+    #[error("Purchase limit reached")]
+    PurchaseLimitReached {},
+
+    #[error("All tokens purchased")]
+    AllTokensPurchased {},
+
+    #[error("Token not available")]
+    TokenNotAvailable {},
+
+    #[error("Cannot mint after sale conducted")]
+    CannotMintAfterSaleConducted {},
+
+    #[error("Too many mint messages, limit is {limit}")]
+    TooManyMintMessages { limit: u32 },
+
+    #[error("Mint limit per block reached, limit is {limit}")]
+    MintLimitPerBlockReached { limit: u32 },
+}
+
+impl From<OverflowError> for ContractError {
+    fn from(err: OverflowError) -> Self {
+        ContractError::Std(StdError::from(err))
+    }
+}
+
+/// Andromeda's AMP handling expects its own error type, so errors leaving the contract through it
+/// are converted back, keeping Andromeda's own errors as they were.
+impl From<ContractError> for AndrContractError {
+    fn from(err: ContractError) -> Self {
+        match err {
+            ContractError::Andromeda(err) => err,
+            ContractError::Std(err) => AndrContractError::Std(err),
+            err => AndrContractError::Std(StdError::generic_err(err.to_string())),
+        }
+    }
 }
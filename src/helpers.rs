@@ -1,7 +1,7 @@
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-use cosmwasm_std::{to_json_binary, Addr, CosmosMsg, StdResult, WasmMsg};
+use cosmwasm_std::{to_json_binary, Addr, Api, CosmosMsg, StdResult, WasmMsg};
 
 use crate::msg::ExecuteMsg;
 
@@ -11,13 +11,19 @@ use crate::msg::ExecuteMsg;
 pub struct CwTemplateContract(pub Addr);
 
 impl CwTemplateContract {
+    /// Wraps `addr` once it has been validated, so that a malformed address is caught here rather
+    /// than when a message is executed.
+    pub fn new(api: &dyn Api, addr: &str) -> StdResult<Self> {
+        Ok(Self(api.addr_validate(addr)?))
+    }
+
     pub fn addr(&self) -> Addr {
         self.0.clone()
     }
 
-    pub fn call<T: Into<ExecuteMsg>>(&self, msg: T) -> StdResult<CosmosMsg>
+    pub fn call<T>(&self, msg: T) -> StdResult<CosmosMsg>
     where
-        T: Serialize + ?Sized,
+        T: Into<ExecuteMsg> + Serialize,
     {
         let binary_msg = to_json_binary(&msg)?;
 
@@ -30,3 +36,19 @@ impl CwTemplateContract {
         Ok(execution_result.into())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cosmwasm_std::testing::MockApi;
+
+    #[test]
+    fn test_new() {
+        let api = MockApi::default();
+        assert_eq!(
+            Addr::unchecked("crowdfund"),
+            CwTemplateContract::new(&api, "crowdfund").unwrap().addr()
+        );
+        assert!(CwTemplateContract::new(&api, "Not An Address").is_err());
+    }
+}
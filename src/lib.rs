@@ -3,5 +3,6 @@ mod error;
 pub mod helpers;
 pub mod msg;
 pub mod state;
+pub mod validation;
 
 pub use crate::error::ContractError;
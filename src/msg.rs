@@ -1,11 +1,372 @@
+use crate::state::{AuditEntry, Config, Purchase, SaleMode, SecondaryApprover, State, TokenOrder};
+use andromeda_non_fungible_tokens::cw721::TokenExtension;
+use andromeda_std::{
+    amp::{recipient::Recipient, AndrAddr},
+    andr_exec, andr_instantiate, andr_instantiate_modules, andr_query,
+    common::MillisecondsExpiration,
+};
 use cosmwasm_schema::{cw_serde, QueryResponses};
+use cosmwasm_std::{Addr, Coin, Decimal, Uint128};
+use cw_utils::Expiration;
 
+#[andr_instantiate]
+#[andr_instantiate_modules]
 #[cw_serde]
-pub struct InstantiateMsg {}
+pub struct InstantiateMsg {
+    /// The address of the token contract whose tokens are being sold.
+    pub token_address: AndrAddr,
+    /// Whether or not the owner can mint additional tokens after the sale has been conducted.
+    pub can_mint_after_sale: bool,
+    /// The maximum number of tokens that can be minted in a single block, unlimited by default.
+    pub max_mints_per_block: Option<u32>,
+    /// The longest a token can be reserved for, in milliseconds, defaults to an hour.
+    pub max_reservation_ttl_ms: Option<u64>,
+}
 
+/// The optional settings of `ExecuteMsg::StartSale`.
 #[cw_serde]
-pub enum ExecuteMsg {}
+#[derive(Default)]
+pub struct SaleOptions {
+    /// How tokens are allocated to buyers, defaults to first come first served.
+    pub sale_mode: Option<SaleMode>,
+    /// Whether `price` already includes taxes, defaults to false. When true the taxes are
+    /// taken out of the price instead of being charged on top of it.
+    pub tax_inclusive: Option<bool>,
+    /// The minimum number of tokens per purchase, unless fewer tokens remain.
+    pub min_purchase: Option<u32>,
+    /// A contract that is sent a `CrowdfundHookMsg::SaleStarted` once the sale has started.
+    pub on_start_hook: Option<AndrAddr>,
+    /// Pays the funds raised so far out to the recipient whenever they reach this amount,
+    /// instead of only when the sale ends. Nothing is paid out before `min_tokens_sold` is
+    /// reached, so that failed sales can still be refunded in full.
+    pub payout_threshold: Option<Uint128>,
+    /// A purchase within this many milliseconds of `end_time` extends the sale by
+    /// `anti_snipe_extension_ms`. Both have to be set to enable the extension.
+    pub anti_snipe_window_ms: Option<u64>,
+    /// How many milliseconds each purchase within the window adds to `end_time`. Extensions add
+    /// up, so the sale ends at most `anti_snipe_max_extension_ms` after its original `end_time`.
+    pub anti_snipe_extension_ms: Option<u64>,
+    /// The most the sale can be extended by in total, defaults to a single extension.
+    pub anti_snipe_max_extension_ms: Option<u64>,
+    /// An oracle quoting the USD rate of `price.denom`. When set together with `usd_price`,
+    /// each purchase is charged the amount of `price.denom` worth `usd_price` at the current
+    /// rate instead of `price.amount`.
+    pub oracle: Option<AndrAddr>,
+    /// The price per token in USD, in the precision of the oracle's rates.
+    pub usd_price: Option<Uint128>,
+    /// The minimum number of distinct purchasers, on top of `min_tokens_sold`, to go through
+    /// with the sale.
+    pub min_purchasers: Option<u32>,
+    /// Whether funds sent on top of the payment for a purchase are refunded, defaults to true.
+    /// When false they are kept for the recipient as a tip, unless the sale fails.
+    pub refund_overpayment: Option<bool>,
+    /// Whether a batch purchase listing the same token id more than once is rejected rather
+    /// than skipping the repeats, defaults to false. Purchases never hold the same id twice
+    /// either way, as a purchased token is no longer available.
+    pub enforce_distinct_tokens: Option<bool>,
+}
 
+#[andr_exec]
+#[cw_serde]
+#[allow(clippy::large_enum_variant)]
+pub enum ExecuteMsg {
+    /// Mints a new token to be sold in a future sale. Only possible when the sale is not ongoing.
+    Mint(Vec<CrowdfundMintMsg>),
+    /// Starts the sale if one is not already ongoing.
+    StartSale {
+        /// When the sale starts. Defaults to the current time.
+        start_time: Option<MillisecondsExpiration>,
+        /// When the sale ends.
+        end_time: MillisecondsExpiration,
+        /// The price per token.
+        price: Coin,
+        /// The minimum amount of tokens sold to go through with the sale.
+        min_tokens_sold: Uint128,
+        /// The amount of tokens a wallet can purchase, default is 1.
+        max_amount_per_wallet: Option<u32>,
+        /// The recipient of the funds if the sale met the minimum sold.
+        recipient: Recipient,
+        /// The optional settings of the sale, each of which defaults when omitted.
+        #[serde(default)]
+        options: SaleOptions,
+    },
+    /// Purchases tokens in an ongoing sale.
+    Purchase {
+        number_of_tokens: Option<u32>,
+        /// Who the tokens are purchased for, defaults to the sender. The tokens count towards
+        /// the recipient's wallet limit and the recipient receives them, or the refund if the sale
+        /// fails.
+        recipient: Option<String>,
+    },
+    /// Purchases the token with the given id.
+    PurchaseByTokenId {
+        token_id: String,
+        /// Who the token is purchased for, defaults to the sender.
+        recipient: Option<String>,
+    },
+    /// Purchases the tokens with the given ids. When `allow_partial` is true, the ids that can't
+    /// be purchased are skipped and their payment is refunded, otherwise the whole purchase fails.
+    BatchPurchaseByTokenId {
+        token_ids: Vec<String>,
+        allow_partial: bool,
+        /// Who the tokens are purchased for, defaults to the sender.
+        recipient: Option<String>,
+    },
+    /// Reserves the available token with the given id for the sender for `ttl_ms` milliseconds,
+    /// during which nobody else can purchase it. The sender can't reserve more tokens than they
+    /// are still allowed to purchase.
+    ReserveToken { token_id: String, ttl_ms: u64 },
+    /// Releases the sender's reservation of the token with the given id.
+    ReleaseReservation { token_id: String },
+    /// Removes the expired reservations among the next `limit` reservations. Anyone can sweep.
+    SweepExpiredReservations { limit: Option<u32> },
+    /// Removes the purchasers among the next `limit` purchasers that are owed nothing, which are
+    /// all of them once the sale is over. Only possible when no sale is ongoing or when the ended
+    /// sale is refunding. Anyone can prune.
+    PrunePurchases { limit: Option<u32> },
+    /// Deposits funds for `number_of_tokens` tickets in an ongoing lottery sale. Each ticket costs
+    /// the price and the tax on it.
+    Deposit { number_of_tokens: Option<u32> },
+    /// Refunds the sender's deposits in an ended lottery sale whose draw hasn't started, so that
+    /// deposits are never locked by a draw that doesn't happen.
+    WithdrawDeposit {},
+    /// Allocates the available tokens among the tickets of the next `limit` depositors of an
+    /// ended lottery sale and refunds the tickets that were not drawn. Only the owner can start
+    /// the draw, with `seed`, after which anyone can continue it until every depositor is drawn.
+    Draw { seed: u64, limit: Option<u32> },
+    /// Allows a user to claim their own refund if the minimum number of tokens are not sold.
+    ClaimRefund {},
+    /// Sets the address the sender's refunds are sent to instead of the sender.
+    SetRefundAddress { address: String },
+    /// Refunds up to `limit` purchasers at once if the minimum number of tokens are not sold, so
+    /// that they don't have to claim their refunds themselves. Anyone can push the refunds.
+    RefundAll {
+        start_after: Option<String>,
+        limit: Option<u32>,
+    },
+    /// Ends the ongoing sale by completing `limit` number of operations depending on if the
+    /// minimum number of tokens was sold.
+    EndSale { limit: Option<u32> },
+    /// Ends the ongoing sale right away and refunds every purchaser, even if the minimum number
+    /// of tokens was sold. Refunds and burns `limit` purchasers and tokens per call, until the
+    /// sale is over. Only the owner can abort, and only before any funds were paid out.
+    AbortSale { limit: Option<u32> },
+    /// Declines the sender's purchase of `token_id` from a successful sale, refunding its price
+    /// and tax and making the token available again. Only possible after the sale has ended and
+    /// before any tokens have been transferred, and only while the sale still meets its minimum
+    /// afterwards.
+    DeclinePurchase { token_id: String },
+    /// Updates the token contract. Only possible before any tokens have been minted. When a
+    /// secondary approver is set, this only proposes the update.
+    UpdateTokenContract { address: AndrAddr },
+    /// Sets whether the owner can mint additional tokens after a sale has been conducted. Only
+    /// possible when no sale is ongoing.
+    UpdateCanMintAfterSale { value: bool },
+    /// Sets the secondary approver that has to confirm high value admin actions, or removes it.
+    /// Only the owner can set it. Once an approver is set, changing or removing it is itself a
+    /// proposal that the current approver has to confirm.
+    SetSecondaryApprover { approver: Option<SecondaryApprover> },
+    /// Confirms and performs an admin action proposed by the owner. Only the secondary approver
+    /// can confirm, within the confirmation window.
+    ConfirmProposal { proposal_id: u64 },
+    /// Marks tokens that were minted to the contract out-of-band as available for the next sale,
+    /// without minting them. Only possible when no sale is ongoing.
+    SeedAvailability { token_ids: Vec<String> },
+}
+
+/// The query the crowdfund sends to the oracle of an oracle priced sale.
 #[cw_serde]
 #[derive(QueryResponses)]
-pub enum QueryMsg {}
+pub enum OracleQueryMsg {
+    /// The USD value of one unit of `denom`.
+    #[returns(OracleRateResponse)]
+    Rate { denom: String },
+}
+
+#[cw_serde]
+pub struct OracleRateResponse {
+    pub rate: Decimal,
+}
+
+/// The messages the crowdfund sends to the hooks configured for a sale.
+#[cw_serde]
+pub enum CrowdfundHookMsg {
+    SaleStarted {
+        start_time: Expiration,
+        end_time: Expiration,
+        price: Coin,
+        min_tokens_sold: Uint128,
+        max_amount_per_wallet: u32,
+        total_tokens: Uint128,
+    },
+}
+
+#[cw_serde]
+pub struct CrowdfundMintMsg {
+    /// Unique ID of the NFT.
+    pub token_id: String,
+    /// The owner of the newly minted NFT. Defaults to the crowdfund contract.
+    pub owner: Option<String>,
+    /// Universal resource identifier for this NFT.
+    /// Should point to a JSON file that conforms to the ERC721 Metadata JSON Schema. Can be
+    /// omitted for fully on-chain metadata, as long as the extension is set.
+    pub token_uri: Option<String>,
+    /// Any custom extension used by this contract.
+    pub extension: TokenExtension,
+}
+
+#[andr_query]
+#[cw_serde]
+#[derive(QueryResponses)]
+pub enum QueryMsg {
+    /// The state of the ongoing sale, or `None` when there is no sale.
+    #[returns(Option<State>)]
+    State {},
+    /// The denom tokens are priced in during the ongoing sale, or `None` when there is no sale.
+    #[returns(Option<String>)]
+    SaleDenom {},
+    /// Where the funds of the ongoing sale are paid out to, or `None` when there is no sale.
+    #[returns(Option<PayoutDestinationResponse>)]
+    PayoutDestination {},
+    /// The price per token of the ongoing sale for display, or `None` when there is no sale.
+    #[returns(Option<PriceDisplayResponse>)]
+    PriceDisplay {},
+    /// Whether the ongoing sale is going to succeed or refund, or `None` when there is no sale.
+    #[returns(Option<ProjectedOutcome>)]
+    ProjectedOutcome {},
+    /// The progress of the ongoing sale in basis points, or `None` when there is no sale.
+    #[returns(Option<ProgressBpsResponse>)]
+    ProgressBps {},
+    /// Whether the contract holds enough of the sale denom to cover what it owes in the ongoing
+    /// sale, or `None` when there is no sale.
+    #[returns(Option<EscrowStatusResponse>)]
+    EscrowStatus {},
+    #[returns(Config)]
+    Config {},
+    /// The config with `token_address` resolved to a concrete address.
+    #[returns(ResolvedConfigResponse)]
+    ResolvedConfig {},
+    #[returns(Vec<String>)]
+    AvailableTokens {
+        start_after: Option<String>,
+        limit: Option<u32>,
+        /// When true, only returns the tokens the token contract reports as still owned by the
+        /// crowdfund contract.
+        verify: Option<bool>,
+        /// The order of the tokens, defaults to by token id.
+        order: Option<TokenOrder>,
+    },
+    #[returns(bool)]
+    IsTokenAvailable { id: String },
+    /// Whether the token is available, has been purchased in the ongoing sale, or neither, e.g.
+    /// because it was transferred or burned.
+    #[returns(TokenStatus)]
+    TokenStatus { id: String },
+    /// Whether every token of the ongoing sale has been purchased. False when there is no sale.
+    #[returns(bool)]
+    IsSoldOut {},
+    /// Whether the owner can currently mint tokens, i.e. no sale is ongoing and either no sale has
+    /// been conducted yet or minting after a sale is allowed.
+    #[returns(bool)]
+    CanMint {},
+    /// How many more tokens `purchaser` may buy in the ongoing sale.
+    #[returns(RemainingAllowanceResponse)]
+    RemainingAllowance { purchaser: String },
+    /// The purchases made by `purchaser` in the ongoing sale.
+    #[returns(Vec<Purchase>)]
+    Purchases { purchaser: String },
+    /// The tax `purchaser` paid on each token of the ongoing sale. This can differ between
+    /// tokens, as the rates can change during the sale.
+    #[returns(Vec<PurchaseTaxResponse>)]
+    PurchaseTaxes { purchaser: String },
+    /// What `purchaser` would be refunded if the ongoing sale fails, with one coin per denom.
+    #[returns(Vec<Coin>)]
+    RefundBreakdown { purchaser: String },
+    /// The admin actions performed on the contract, oldest first.
+    #[returns(Vec<AuditEntry>)]
+    AuditLog {
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    },
+}
+
+#[cw_serde]
+pub struct ResolvedConfigResponse {
+    /// The resolved address of the token contract whose tokens are being sold.
+    pub token_address: Addr,
+    /// Whether or not the owner can mint additional tokens after the sale has been conducted.
+    pub can_mint_after_sale: bool,
+}
+
+#[cw_serde]
+pub struct PayoutDestinationResponse {
+    /// The resolved address of the recipient.
+    pub address: Addr,
+    /// Whether the payouts are routed through the kernel with the recipient's AMP message.
+    pub is_amp: bool,
+}
+
+#[cw_serde]
+pub struct PriceDisplayResponse {
+    /// The price per token, in the smallest unit of its denom.
+    pub price: Coin,
+    /// The denom of `price`. The contract doesn't know the denom's decimals, so formatting the
+    /// amount is left to the client.
+    pub denom: String,
+}
+
+#[cw_serde]
+pub struct PurchaseTaxResponse {
+    /// The purchased token.
+    pub token_id: String,
+    /// The tax paid on top of the price of the token.
+    pub tax_amount: Uint128,
+}
+
+#[cw_serde]
+pub struct ProgressBpsResponse {
+    /// The share of `total_tokens` that has been sold.
+    pub sold_bps: u64,
+    /// The share of `min_tokens_sold` that has been sold, at most 10000.
+    pub min_bps: u64,
+}
+
+#[cw_serde]
+pub enum TokenStatus {
+    Available,
+    Sold { purchaser: String },
+    Unknown,
+}
+
+#[cw_serde]
+pub struct EscrowStatusResponse {
+    /// The contract's balance of the sale denom.
+    pub balance: Coin,
+    /// The amount to send to the recipient if the sale succeeds.
+    pub amount_to_send: Uint128,
+    /// The amount to refund to the purchasers if the sale fails.
+    pub pending_refunds: Uint128,
+    /// How much the balance falls short of what is owed, zero when it is covered. That is the
+    /// larger of the two obligations, or only `amount_to_send` once the minimum has been sold.
+    pub shortfall: Uint128,
+}
+
+#[cw_serde]
+pub enum ProjectedOutcome {
+    /// The minimum number of tokens has been sold.
+    WillSucceed,
+    /// The minimum number of tokens can no longer be sold, either because the sale has ended or
+    /// because not enough tokens are left.
+    WillRefund,
+    /// The minimum number of tokens has not been sold yet, but still can be.
+    Undetermined,
+}
+
+#[cw_serde]
+pub struct RemainingAllowanceResponse {
+    /// The number of tokens the purchaser can still buy before reaching the per wallet limit.
+    pub remaining: u32,
+    /// Whether a purchase would currently go through, given the sale is ongoing and tokens are
+    /// still available.
+    pub can_purchase: bool,
+}
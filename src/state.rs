@@ -1,8 +1,9 @@
-use andromeda_non_fungible_tokens::crowdfund::{Config, State};
-use andromeda_std::error::ContractError;
+use crate::ContractError;
+use andromeda_std::amp::{recipient::Recipient, AndrAddr};
 use cosmwasm_schema::cw_serde;
-use cosmwasm_std::{Order, Storage, SubMsg, Uint128};
+use cosmwasm_std::{BlockInfo, Coin, Order, StdResult, Storage, SubMsg, Timestamp, Uint128};
 use cw_storage_plus::{Bound, Item, Map};
+use cw_utils::Expiration;
 
 /// The config.
 pub const CONFIG: Item<Config> = Item::new("config");
@@ -16,23 +17,295 @@ pub const STATE: Item<State> = Item::new("state");
 /// Relates buyer address to vector of purchases.
 pub const PURCHASES: Map<&str, Vec<Purchase>> = Map::new("buyers");
 
+/// Relates token id to the purchaser whose purchase of it is in `PURCHASES`.
+pub const TOKEN_PURCHASERS: Map<&str, String> = Map::new("token_purchasers");
+
 /// Contains token ids that have not been purchased.
 pub const AVAILABLE_TOKENS: Map<&str, bool> = Map::new("available_tokens");
 
+/// Relates token id to the order it was made available in.
+pub const TOKEN_SEQUENCES: Map<&str, u64> = Map::new("token_sequences");
+
+/// Contains the token ids of `AVAILABLE_TOKENS` by the order they were made available in.
+pub const AVAILABLE_TOKENS_BY_SEQUENCE: Map<u64, String> = Map::new("available_tokens_by_sequence");
+
+/// The sequence of the next token made available.
+pub const NEXT_TOKEN_SEQUENCE: Item<u64> = Item::new("next_token_sequence");
+
+/// Relates token id to the reservation holding it for a single purchaser.
+pub const RESERVATIONS: Map<&str, Reservation> = Map::new("reservations");
+
+/// Relates reserver address and token id to when the reservation of `RESERVATIONS` lapses, so
+/// that the reservations of a single reserver can be counted.
+pub const RESERVER_TOKENS: Map<(&str, &str), Expiration> = Map::new("reserver_tokens");
+
+/// Relates depositor address to the deposits made for tickets in a lottery sale.
+pub const DEPOSITS: Map<&str, Vec<Deposit>> = Map::new("deposits");
+
+/// The number of tickets in `DEPOSITS`.
+pub const NUMBER_OF_TICKETS: Item<u32> = Item::new("number_of_tickets");
+
+/// The state of the random sequence of a draw that has started but not yet gone through every
+/// deposit.
+pub const DRAW_RNG: Item<u64> = Item::new("draw_rng");
+
+/// Relates denom to the revenue collected in that denom which is yet to be sent to the
+/// recipient.
+pub const REVENUE: Map<&str, Uint128> = Map::new("revenue");
+
+/// Relates payout reply id to the funds sent by that payout, so that they can be restored if
+/// the payout fails.
+pub const PENDING_PAYOUTS: Map<u64, Coin> = Map::new("pending_payouts");
+
+/// Relates purchaser address to the address their refunds are sent to instead.
+pub const REFUND_ADDRESSES: Map<&str, String> = Map::new("refund_addresses");
+
+/// Relates refund reply id to the purchases refunded by it, so that they can be restored if the
+/// refund fails.
+pub const PENDING_REFUNDS: Map<u64, Vec<Purchase>> = Map::new("pending_refunds");
+
+/// The address that has to confirm high value admin actions proposed by the owner, if any.
+pub const SECONDARY_APPROVER: Item<SecondaryApprover> = Item::new("secondary_approver");
+
+/// Relates proposal id to an admin action awaiting confirmation by the secondary approver.
+pub const PROPOSALS: Map<u64, Proposal> = Map::new("proposals");
+
+/// The id of the next proposal. Ids are never reused, even once proposals are removed.
+pub const NEXT_PROPOSAL_ID: Item<u64> = Item::new("next_proposal_id");
+
+/// Relates audit entry id to the admin action it records. Ids are sequential, so entries are
+/// ordered by when they happened.
+pub const AUDIT_LOG: Map<u64, AuditEntry> = Map::new("audit_log");
+
+/// The block height of the latest mint and the number of tokens minted in that block.
+pub const MINTS_IN_BLOCK: Item<(u64, u32)> = Item::new("mints_in_block");
+
 /// Is set to true when at least one sale has been conducted. This is used to disallow minting if
 /// config.can_mint_after_sale is false.
 pub const SALE_CONDUCTED: Item<bool> = Item::new("sale_conducted");
 
+#[cw_serde]
+pub struct Config {
+    /// The address of the token contract whose tokens are being sold.
+    pub token_address: AndrAddr,
+    /// Whether or not the owner can mint additional tokens after the sale has been conducted.
+    pub can_mint_after_sale: bool,
+    /// The maximum number of tokens that can be minted in a single block.
+    pub max_mints_per_block: Option<u32>,
+    /// The longest a token can be reserved for, in milliseconds.
+    pub max_reservation_ttl_ms: Option<u64>,
+}
+
+#[cw_serde]
+pub struct State {
+    /// The expiration denoting when the sale started.
+    pub start_time: Expiration,
+    /// The expiration denoting when the sale ends.
+    pub end_time: Expiration,
+    /// The price of each token. For oracle priced sales, this is the price of the latest purchase.
+    pub price: Coin,
+    /// The minimum number of tokens sold for the sale to go through.
+    pub min_tokens_sold: Uint128,
+    /// The max number of tokens allowed per wallet.
+    pub max_amount_per_wallet: u32,
+    /// Number of tokens sold.
+    pub amount_sold: Uint128,
+    /// The amount of funds to send to recipient if sale successful. This already
+    /// takes into account the royalties and taxes.
+    pub amount_to_send: Uint128,
+    /// Number of tokens transferred to purchasers if sale was successful.
+    pub amount_transferred: Uint128,
+    /// The recipient of the raised funds if the sale is successful.
+    pub recipient: Recipient,
+    /// The number of tokens available when the sale started.
+    pub total_tokens: Uint128,
+    /// The percentage of `total_tokens` which ends the sale once sold.
+    pub target_percentage_sold: Option<u128>,
+    /// The maximum duration of the sale, in seconds.
+    pub max_duration: Option<u64>,
+    /// Whether the owner has ended the sale.
+    pub owner_ended: bool,
+    /// The resolved address of the token contract being sold.
+    pub token_address: String,
+    /// How tokens are allocated to buyers.
+    pub sale_mode: SaleMode,
+    /// Whether taxes are included in `price` rather than charged on top of it.
+    pub tax_inclusive: bool,
+    /// The minimum number of tokens per purchase, unless fewer tokens remain.
+    pub min_purchase: Option<u32>,
+    /// The value of `amount_to_send` at which the funds raised so far are paid out during the
+    /// sale, once the minimum number of tokens has been sold.
+    pub payout_threshold: Option<Uint128>,
+    /// How late purchases extend the sale.
+    pub anti_snipe: Option<AntiSnipe>,
+    /// The oracle pricing the sale, if the price per token is set in USD.
+    pub oracle: Option<OraclePrice>,
+    /// Whether the owner has aborted the sale, refunding every purchaser.
+    pub aborted: bool,
+    /// The minimum number of distinct purchasers for the sale to go through.
+    pub min_purchasers: Option<u32>,
+    /// Number of distinct purchasers.
+    pub number_of_purchasers: u32,
+    /// Whether overpayments are refunded rather than kept for the recipient.
+    pub refund_overpayment: bool,
+    /// Whether batch purchases listing a token id more than once are rejected.
+    pub enforce_distinct_tokens: bool,
+    /// Funds received for purchases in the denom of `price`, including their tax and tips.
+    pub amount_received: Uint128,
+    /// Funds refunded to purchasers in the denom of `price`.
+    pub amount_refunded: Uint128,
+}
+
+#[cw_serde]
+pub struct OraclePrice {
+    /// The resolved address of the oracle.
+    pub address: String,
+    /// The price per token in USD.
+    pub usd_price: Uint128,
+}
+
+#[cw_serde]
+pub struct AntiSnipe {
+    /// A purchase within this many milliseconds of the end of the sale extends it.
+    pub window_ms: u64,
+    /// How many milliseconds each extension adds to the sale.
+    pub extension_ms: u64,
+    /// The most the sale can be extended by in total, in milliseconds.
+    pub max_extension_ms: u64,
+    /// How many milliseconds the sale has been extended by so far.
+    pub extended_ms: u64,
+}
+
+#[cw_serde]
+pub struct Deposit {
+    /// The number of tickets deposited for.
+    pub number_of_tickets: u32,
+    /// Amount of tax paid for each ticket, in the denom of the price.
+    pub tax_amount: Uint128,
+    /// The part of the price of each ticket credited to the recipient if the ticket is drawn.
+    pub amount_to_send: Uint128,
+    /// sub messages for sending funds for rates for each ticket that is drawn.
+    pub msgs: Vec<SubMsg>,
+}
+
+#[cw_serde]
+pub struct Reservation {
+    /// The address the token is reserved for.
+    pub reserver: String,
+    /// When the reservation lapses.
+    pub expiration: Expiration,
+}
+
+#[cw_serde]
+#[derive(Default)]
+pub enum SaleMode {
+    /// Tokens go to whoever purchases them first.
+    #[default]
+    FirstComeFirstServed,
+    /// Buyers deposit funds during the sale and the available tokens are drawn among them
+    /// once it has ended.
+    Lottery,
+}
+
+/// The order in which available tokens are listed.
+#[cw_serde]
+#[derive(Default)]
+pub enum TokenOrder {
+    /// By token id, in lexical order.
+    #[default]
+    TokenId,
+    /// By the order the tokens were minted or seeded in.
+    Minted,
+}
+
 #[cw_serde]
 pub struct Purchase {
     /// The token id being purchased.
     pub token_id: String,
-    /// Amount of tax paid.
+    /// The price paid for the token, without tax.
+    pub price: Coin,
+    /// Amount of tax paid, in the denom of `price`.
     pub tax_amount: Uint128,
+    /// The part of `price` credited to the recipient, after royalties and any included tax.
+    pub amount_to_send: Uint128,
+    /// Overpayment kept for the recipient, in the denom of `price`.
+    pub tip_amount: Uint128,
     /// sub messages for sending funds for rates.
     pub msgs: Vec<SubMsg>,
     /// The purchaser of the token.
     pub purchaser: String,
+    /// The block time at which the purchase was made.
+    pub purchased_at: Timestamp,
+}
+
+#[cw_serde]
+pub struct SecondaryApprover {
+    /// The address of the approver.
+    pub address: String,
+    /// How long the approver has to confirm a proposal, in seconds.
+    pub confirmation_window_seconds: u64,
+}
+
+#[cw_serde]
+pub struct Proposal {
+    /// The admin action to perform once confirmed.
+    pub action: ProposedAction,
+    /// When the proposal can no longer be confirmed.
+    pub expiration: Expiration,
+}
+
+#[cw_serde]
+pub enum ProposedAction {
+    UpdateTokenContract { address: AndrAddr },
+    SetSecondaryApprover { approver: Option<SecondaryApprover> },
+}
+
+#[cw_serde]
+pub struct AuditEntry {
+    /// The sequential id of the entry.
+    pub id: u64,
+    /// The block time at which the action was performed.
+    pub timestamp: Timestamp,
+    /// The address that performed the action.
+    pub actor: String,
+    /// The action performed, matching the `action` attribute of its response.
+    pub action: String,
+}
+
+/// The layout of `Config` before it was extended, only used to migrate it.
+pub(crate) const LEGACY_CONFIG: Item<LegacyConfig> = Item::new("config");
+
+#[cw_serde]
+pub(crate) struct LegacyConfig {
+    pub token_address: AndrAddr,
+    pub can_mint_after_sale: bool,
+}
+
+/// The layout of `State` before the sale was extended, only used to migrate ongoing sales.
+pub(crate) const LEGACY_STATE: Item<LegacyState> = Item::new("state");
+
+/// The layout of `PURCHASES` before purchases recorded their time, only used to migrate ongoing
+/// sales.
+pub(crate) const LEGACY_PURCHASES: Map<&str, Vec<LegacyPurchase>> = Map::new("buyers");
+
+#[cw_serde]
+pub(crate) struct LegacyState {
+    pub end_time: Expiration,
+    pub price: Coin,
+    pub min_tokens_sold: Uint128,
+    pub max_amount_per_wallet: u32,
+    pub amount_sold: Uint128,
+    pub amount_to_send: Uint128,
+    pub amount_transferred: Uint128,
+    pub recipient: Recipient,
+}
+
+#[cw_serde]
+pub(crate) struct LegacyPurchase {
+    pub token_id: String,
+    pub tax_amount: Uint128,
+    pub msgs: Vec<SubMsg>,
+    pub purchaser: String,
 }
 
 const MAX_LIMIT: u32 = 50;
@@ -41,8 +314,20 @@ pub(crate) fn get_available_tokens(
     storage: &dyn Storage,
     start_after: Option<String>,
     limit: Option<u32>,
+    order: &TokenOrder,
 ) -> Result<Vec<String>, ContractError> {
     let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    if *order == TokenOrder::Minted {
+        let start = start_after
+            .map(|token_id| TOKEN_SEQUENCES.load(storage, &token_id))
+            .transpose()?
+            .map(Bound::exclusive);
+        return Ok(AVAILABLE_TOKENS_BY_SEQUENCE
+            .range(storage, start, None, Order::Ascending)
+            .take(limit)
+            .map(|item| item.map(|(_, token_id)| token_id))
+            .collect::<StdResult<_>>()?);
+    }
     let start = start_after.as_deref().map(Bound::exclusive);
     let tokens: Result<Vec<String>, ContractError> = AVAILABLE_TOKENS
         .keys(storage, start, None, Order::Ascending)
@@ -51,3 +336,179 @@ pub(crate) fn get_available_tokens(
         .collect();
     tokens
 }
+
+/// Marks `token_id` as available, after the tokens made available before it.
+pub(crate) fn add_available_token(
+    storage: &mut dyn Storage,
+    token_id: &str,
+) -> Result<(), ContractError> {
+    AVAILABLE_TOKENS.save(storage, token_id, &true)?;
+    let sequence = NEXT_TOKEN_SEQUENCE.may_load(storage)?.unwrap_or_default();
+    NEXT_TOKEN_SEQUENCE.save(storage, &(sequence + 1))?;
+    TOKEN_SEQUENCES.save(storage, token_id, &sequence)?;
+    AVAILABLE_TOKENS_BY_SEQUENCE.save(storage, sequence, &token_id.to_string())?;
+    Ok(())
+}
+
+pub(crate) fn remove_available_token(
+    storage: &mut dyn Storage,
+    token_id: &str,
+) -> Result<(), ContractError> {
+    AVAILABLE_TOKENS.remove(storage, token_id);
+    if let Some(sequence) = TOKEN_SEQUENCES.may_load(storage, token_id)? {
+        AVAILABLE_TOKENS_BY_SEQUENCE.remove(storage, sequence);
+    }
+    Ok(())
+}
+
+/// Returns up to `limit` available tokens, skipping those reserved for anyone other than
+/// `purchaser`.
+pub(crate) fn get_purchasable_tokens(
+    storage: &dyn Storage,
+    block: &BlockInfo,
+    purchaser: &str,
+    limit: u32,
+) -> Result<Vec<String>, ContractError> {
+    let limit = limit.min(MAX_LIMIT) as usize;
+    let mut tokens = vec![];
+    for token_id in AVAILABLE_TOKENS.keys(storage, None, None, Order::Ascending) {
+        if tokens.len() >= limit {
+            break;
+        }
+        let token_id = token_id?;
+        if !is_reserved_for_other(storage, block, &token_id, purchaser)? {
+            tokens.push(token_id);
+        }
+    }
+    Ok(tokens)
+}
+
+/// Reserves `token_id` with `reservation`, replacing any previous reservation of it.
+pub(crate) fn save_reservation(
+    storage: &mut dyn Storage,
+    token_id: &str,
+    reservation: &Reservation,
+) -> StdResult<()> {
+    remove_reservation(storage, token_id)?;
+    RESERVATIONS.save(storage, token_id, reservation)?;
+    RESERVER_TOKENS.save(
+        storage,
+        (&reservation.reserver, token_id),
+        &reservation.expiration,
+    )
+}
+
+/// Removes the reservation of `token_id`, if any.
+pub(crate) fn remove_reservation(storage: &mut dyn Storage, token_id: &str) -> StdResult<()> {
+    if let Some(reservation) = RESERVATIONS.may_load(storage, token_id)? {
+        RESERVER_TOKENS.remove(storage, (&reservation.reserver, token_id));
+        RESERVATIONS.remove(storage, token_id);
+    }
+    Ok(())
+}
+
+/// Whether `token_id` is held by an unexpired reservation for anyone other than `purchaser`.
+pub(crate) fn is_reserved_for_other(
+    storage: &dyn Storage,
+    block: &BlockInfo,
+    token_id: &str,
+    purchaser: &str,
+) -> StdResult<bool> {
+    Ok(RESERVATIONS
+        .may_load(storage, token_id)?
+        .is_some_and(|reservation| {
+            reservation.reserver != purchaser && !reservation.expiration.is_expired(block)
+        }))
+}
+
+/// The number of tickets `depositor` has deposited for.
+pub(crate) fn get_number_of_tickets(storage: &dyn Storage, depositor: &str) -> StdResult<u32> {
+    Ok(DEPOSITS
+        .may_load(storage, depositor)?
+        .unwrap_or_default()
+        .iter()
+        .map(|deposit| deposit.number_of_tickets)
+        .sum())
+}
+
+/// Appends an entry for `action` performed by `actor` to the audit log.
+pub(crate) fn append_audit_entry(
+    storage: &mut dyn Storage,
+    timestamp: Timestamp,
+    actor: &str,
+    action: &str,
+) -> Result<(), ContractError> {
+    let id = AUDIT_LOG
+        .keys(storage, None, None, Order::Descending)
+        .next()
+        .transpose()?
+        .map_or(0, |last_id| last_id + 1);
+    AUDIT_LOG.save(
+        storage,
+        id,
+        &AuditEntry {
+            id,
+            timestamp,
+            actor: actor.to_string(),
+            action: action.to_string(),
+        },
+    )?;
+    Ok(())
+}
+
+pub(crate) fn get_audit_log(
+    storage: &dyn Storage,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+) -> Result<Vec<AuditEntry>, ContractError> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+    let start = start_after.map(Bound::exclusive);
+    let entries: StdResult<Vec<AuditEntry>> = AUDIT_LOG
+        .range(storage, start, None, Order::Ascending)
+        .take(limit)
+        .map(|item| item.map(|(_, entry)| entry))
+        .collect();
+    Ok(entries?)
+}
+
+/// Adds `funds` to the revenue collected in their denom.
+pub(crate) fn add_revenue(storage: &mut dyn Storage, funds: &Coin) -> Result<(), ContractError> {
+    REVENUE.update(
+        storage,
+        &funds.denom,
+        |amount| -> Result<_, ContractError> {
+            Ok(amount.unwrap_or_default().checked_add(funds.amount)?)
+        },
+    )?;
+    Ok(())
+}
+
+/// Subtracts `funds` from the revenue collected in their denom.
+pub(crate) fn subtract_revenue(
+    storage: &mut dyn Storage,
+    funds: &Coin,
+) -> Result<(), ContractError> {
+    REVENUE.update(
+        storage,
+        &funds.denom,
+        |amount| -> Result<_, ContractError> {
+            Ok(amount.unwrap_or_default().checked_sub(funds.amount)?)
+        },
+    )?;
+    Ok(())
+}
+
+/// Removes and returns the revenue collected so far, one coin per denom.
+pub(crate) fn take_revenue(storage: &mut dyn Storage) -> Result<Vec<Coin>, ContractError> {
+    let revenue: Vec<Coin> = REVENUE
+        .range(storage, None, None, Order::Ascending)
+        .map(|item| item.map(|(denom, amount)| Coin { denom, amount }))
+        .collect::<StdResult<_>>()?;
+    for coin in revenue.iter() {
+        REVENUE.remove(storage, &coin.denom);
+    }
+    Ok(revenue
+        .into_iter()
+        .filter(|coin| !coin.amount.is_zero())
+        .collect())
+}
@@ -0,0 +1,155 @@
+use crate::ContractError;
+use cosmwasm_std::ensure;
+use cw_utils::Expiration;
+
+/// The basis points of a whole.
+pub const MAX_BPS: u64 = 10_000;
+
+/// Ensures that `value`, given as the `field` of a message, is not empty.
+pub fn validate_non_empty(field: &str, value: &str) -> Result<(), ContractError> {
+    ensure!(
+        !value.trim().is_empty(),
+        ContractError::CustomError {
+            msg: format!("{field} must not be empty"),
+        }
+    );
+    Ok(())
+}
+
+/// Ensures that `denom`, given as the `field` of a message, is a valid bank denom. Like in the
+/// Cosmos SDK, that is a letter followed by 2 to 127 letters, digits or any of `/:._-`.
+pub fn validate_denom(field: &str, denom: &str) -> Result<(), ContractError> {
+    validate_non_empty(field, denom)?;
+    let is_valid = (3..=128).contains(&denom.len())
+        && denom.starts_with(|c: char| c.is_ascii_alphabetic())
+        && denom
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "/:._-".contains(c));
+    ensure!(is_valid, ContractError::InvalidDenom {});
+    Ok(())
+}
+
+/// Ensures that `url`, given as the `field` of a message, is an absolute URL, i.e. a scheme such
+/// as `https` or `ipfs` followed by `://` and the rest of the URL, without any whitespace.
+pub fn validate_url(field: &str, url: &str) -> Result<(), ContractError> {
+    validate_non_empty(field, url)?;
+    let is_valid = match url.split_once("://") {
+        Some((scheme, rest)) => {
+            scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+                && scheme
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || "+.-".contains(c))
+                && !rest.is_empty()
+                && !url.contains(char::is_whitespace)
+        }
+        None => false,
+    };
+    ensure!(
+        is_valid,
+        ContractError::CustomError {
+            msg: format!("{field} {url} is not a valid URL"),
+        }
+    );
+    Ok(())
+}
+
+/// Ensures that `bps`, given as the `field` of a message, is at most `MAX_BPS`, i.e. a whole.
+pub fn validate_bps(field: &str, bps: u64) -> Result<(), ContractError> {
+    ensure!(
+        bps <= MAX_BPS,
+        ContractError::CustomError {
+            msg: format!("{field} must not exceed {MAX_BPS} bps"),
+        }
+    );
+    Ok(())
+}
+
+/// Ensures that `start` comes before `end`.
+pub fn validate_date_range(start: &Expiration, end: &Expiration) -> Result<(), ContractError> {
+    ensure!(end > start, ContractError::StartTimeAfterEndTime {});
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cosmwasm_std::Timestamp;
+
+    #[test]
+    fn test_validate_non_empty() {
+        assert!(validate_non_empty("denom", "uusd").is_ok());
+        assert_eq!(
+            ContractError::CustomError {
+                msg: "denom must not be empty".to_string(),
+            },
+            validate_non_empty("denom", " ").unwrap_err()
+        );
+    }
+
+    #[test]
+    fn test_validate_denom() {
+        for denom in [
+            "uusd",
+            "ibc/27394FB092D2ECCD56123C74F36E4C1F926001CEADA9CA97EA622B25F41E5EB2",
+        ] {
+            assert!(validate_denom("denom", denom).is_ok());
+        }
+        for denom in ["1uusd", "us", "u usd", "uusd!"] {
+            assert_eq!(
+                ContractError::InvalidDenom {},
+                validate_denom("denom", denom).unwrap_err()
+            );
+        }
+        assert_eq!(
+            ContractError::InvalidDenom {},
+            validate_denom("denom", &"u".repeat(129)).unwrap_err()
+        );
+    }
+
+    #[test]
+    fn test_validate_url() {
+        for url in ["https://example.com/token/1.json", "ipfs://bafybeigdyrzt"] {
+            assert!(validate_url("token_uri", url).is_ok());
+        }
+        for url in [
+            "example.com",
+            "https://",
+            "1ftp://example.com",
+            "https://exa mple.com",
+        ] {
+            assert_eq!(
+                ContractError::CustomError {
+                    msg: format!("token_uri {url} is not a valid URL"),
+                },
+                validate_url("token_uri", url).unwrap_err()
+            );
+        }
+    }
+
+    #[test]
+    fn test_validate_bps() {
+        assert!(validate_bps("sold_bps", 0).is_ok());
+        assert!(validate_bps("sold_bps", MAX_BPS).is_ok());
+        assert_eq!(
+            ContractError::CustomError {
+                msg: "sold_bps must not exceed 10000 bps".to_string(),
+            },
+            validate_bps("sold_bps", MAX_BPS + 1).unwrap_err()
+        );
+    }
+
+    #[test]
+    fn test_validate_date_range() {
+        let start = Expiration::AtTime(Timestamp::from_seconds(100));
+        let end = Expiration::AtTime(Timestamp::from_seconds(200));
+        assert!(validate_date_range(&start, &end).is_ok());
+        assert_eq!(
+            ContractError::StartTimeAfterEndTime {},
+            validate_date_range(&end, &start).unwrap_err()
+        );
+        assert_eq!(
+            ContractError::StartTimeAfterEndTime {},
+            validate_date_range(&start, &start).unwrap_err()
+        );
+    }
+}